@@ -1,10 +1,25 @@
 use clap::{Parser, Subcommand};
-use hashassin_client::{crack as client_crack, upload};
+use hashassin_client::{
+    ClientConfig, ClientError, Status, cancel as client_cancel, crack as client_crack,
+    delete as client_delete, download as client_download, info as client_info,
+    job_status as client_job_status, list as client_list, result as client_result,
+    status as client_status, submit as client_submit, upload,
+};
 use hashassin_core::{
-    crack, dump_hashes, dump_rainbow_table, gen_hashes, gen_passwords, gen_rainbow_table,
+    ALGORITHMS, BenchConfig, CancelToken, ChainStarts, Hooks, Progress, SplitBy, TableParams,
+    analyze_table, convert_file, crack, dump_hashes, dump_rainbow_table, gen_hashes, gen_passwords,
+    gen_rainbow_table, identify, identify_file, inspect, merge_tables, plan_table, run_benchmark,
+    split_table, verify_table,
+};
+use hashassin_server::{ServerConfig, start_server};
+use indicatif::{ProgressBar, ProgressStyle};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use std::{
+    error::Error,
+    fs::File,
+    io::{LineWriter, Write},
 };
-use hashassin_server::start_server;
-use std::{error::Error, fs::File, io::Write};
 use tracing::info;
 
 #[derive(Parser)]
@@ -12,6 +27,10 @@ use tracing::info;
 struct Cli {
     #[command(subcommand)]
     command: Commands,
+    // File holding the token the client-* commands authenticate with, HASHASSIN_TOKEN when
+    // not given. Tokens are never taken on the command line, where other users can see them
+    #[arg(long, global = true)]
+    token_file: Option<String>,
 }
 
 #[derive(Subcommand)]
@@ -25,6 +44,8 @@ enum Commands {
         threads: usize,
         #[arg(long)]
         out_file: Option<String>,
+        #[arg(long)]
+        seed: Option<u64>,
     },
     GenHashes {
         #[arg(long = "in-file")]
@@ -41,8 +62,12 @@ enum Commands {
         in_file: String,
     },
     GenRainbowTable {
-        #[arg(long = "in-file")]
-        in_file: String,
+        #[arg(long = "in-file", required_unless_present = "chains")]
+        in_file: Option<String>,
+        #[arg(long, conflicts_with = "in_file")]
+        chains: Option<u64>,
+        #[arg(long, requires = "chains")]
+        table_seed: Option<u64>,
         #[arg(long)]
         out_file: String,
         #[arg(long, default_value_t = String::from("md5"))]
@@ -51,11 +76,97 @@ enum Commands {
         num_links: usize,
         #[arg(long, default_value_t = 1)]
         threads: usize,
+        #[arg(long, requires = "max_len")]
+        min_len: Option<usize>,
+        #[arg(long, requires = "min_len")]
+        max_len: Option<usize>,
+        #[arg(long)]
+        compress: bool,
+        #[arg(long)]
+        dp_bits: Option<u8>,
+        #[arg(long)]
+        resume: bool,
+        #[arg(long, default_value_t = 10000)]
+        checkpoint_every: usize,
     },
     DumpRainbowTable {
         #[arg(long = "in-file")]
         in_file: String,
     },
+    Inspect {
+        #[arg(long = "in-file")]
+        in_file: String,
+        #[arg(long, default_value_t = 0)]
+        offset: usize,
+        #[arg(long)]
+        limit: Option<usize>,
+        #[arg(long)]
+        json: bool,
+    },
+    Convert {
+        #[arg(long = "in-file")]
+        in_file: String,
+        #[arg(long)]
+        out_file: String,
+        #[arg(long)]
+        to_version: Option<u8>,
+    },
+    MergeTables {
+        #[arg(long = "in-file", required = true, num_args = 1..)]
+        in_files: Vec<String>,
+        #[arg(long)]
+        out_file: String,
+    },
+    SplitTable {
+        #[arg(long = "in-file")]
+        in_file: String,
+        #[arg(long)]
+        out_prefix: String,
+        #[arg(long, required_unless_present = "prefix_chars")]
+        chains_per_table: Option<usize>,
+        #[arg(long, conflicts_with = "chains_per_table")]
+        prefix_chars: Option<usize>,
+    },
+    VerifyTable {
+        #[arg(long = "in-file")]
+        in_file: String,
+        #[arg(long, default_value_t = 100)]
+        samples: usize,
+    },
+    AnalyzeTable {
+        #[arg(long = "in-file")]
+        in_file: String,
+    },
+    PlanTable {
+        #[arg(long)]
+        min_len: usize,
+        #[arg(long)]
+        max_len: usize,
+        #[arg(long, default_value_t = 0.9)]
+        success: f64,
+        #[arg(long)]
+        num_links: Option<usize>,
+    },
+    Benchmark {
+        #[arg(long, value_delimiter = ',')]
+        algorithms: Vec<String>,
+        #[arg(long, value_delimiter = ',')]
+        threads: Vec<usize>,
+        #[arg(long, value_delimiter = ',', default_value = "8")]
+        lengths: Vec<usize>,
+        #[arg(long, default_value_t = 1000)]
+        duration_ms: u64,
+        #[arg(long, default_value_t = 100)]
+        num_links: usize,
+        #[arg(long)]
+        json: bool,
+    },
+    Identify {
+        #[arg(long, required_unless_present = "in_file")]
+        hash: Option<String>,
+        #[arg(long = "in-file", conflicts_with = "hash")]
+        in_file: Option<String>,
+    },
     Crack {
         #[arg(long = "in-file", alias = "table-file")]
         in_file: String,
@@ -77,6 +188,14 @@ enum Commands {
         async_threads: usize,
         #[arg(long)]
         cache_size: Option<i32>,
+        #[arg(long)]
+        data_dir: Option<String>,
+        #[arg(long, default_value_t = 1 << 30)]
+        max_upload_size: u64,
+        #[arg(long, default_value_t = 64 << 20)]
+        max_crack_size: u64,
+        #[arg(long)]
+        auth_token_file: Option<String>,
     },
     ClientUpload {
         #[arg(long)]
@@ -85,6 +204,8 @@ enum Commands {
         in_file: String,
         #[arg(long)]
         name: String,
+        #[arg(long)]
+        replace: bool,
     },
     ClientCrack {
         #[arg(long)]
@@ -93,12 +214,106 @@ enum Commands {
         in_file: String,
         #[arg(long)]
         out_file: Option<String>,
+        #[arg(long = "table")]
+        tables: Vec<String>,
     },
+    ClientSubmit {
+        #[arg(long)]
+        server: String,
+        #[arg(long = "in-file")]
+        in_file: String,
+        #[arg(long = "table")]
+        tables: Vec<String>,
+    },
+    ClientStatus {
+        #[arg(long)]
+        server: String,
+        #[arg(long)]
+        job: Option<u64>,
+    },
+    ClientResult {
+        #[arg(long)]
+        server: String,
+        #[arg(long)]
+        job: u64,
+        #[arg(long)]
+        out_file: Option<String>,
+        #[arg(long)]
+        wait: bool,
+    },
+    ClientCancel {
+        #[arg(long)]
+        server: String,
+        #[arg(long)]
+        job: u64,
+    },
+    ClientList {
+        #[arg(long)]
+        server: String,
+    },
+    ClientInfo {
+        #[arg(long)]
+        server: String,
+        #[arg(long)]
+        name: String,
+    },
+    ClientDelete {
+        #[arg(long)]
+        server: String,
+        #[arg(long)]
+        name: String,
+    },
+    ClientDownload {
+        #[arg(long)]
+        server: String,
+        #[arg(long)]
+        name: String,
+        #[arg(long)]
+        out_file: String,
+    },
+}
+
+// Progress bar on stderr, fed by the core progress hooks
+struct Bar(ProgressBar);
+
+impl Progress for Bar {
+    fn update(&self, done: u64, total: u64, rate: f64) {
+        self.0.set_length(total);
+        self.0.set_position(done);
+        self.0.set_message(format!("{:.0}/s", rate));
+    }
+}
+
+// Hooks that draw a progress bar and cancel the operation on Ctrl-C
+// The operation then stops early and writes what it has so far
+fn progress_hooks(label: &str) -> Result<(Hooks, ProgressBar), Box<dyn Error>> {
+    let bar = ProgressBar::new(0);
+    bar.set_style(
+        ProgressStyle::with_template("{prefix} [{bar:40}] {pos}/{len} {msg} ETA {eta}")?
+            .progress_chars("=> "),
+    );
+    bar.set_prefix(label.to_string());
+
+    let cancel = CancelToken::new();
+    let token = cancel.clone();
+    ctrlc::set_handler(move || token.cancel())?;
+
+    Ok((Hooks::new(Arc::new(Bar(bar.clone())), cancel), bar))
+}
+
+// The token client-* commands authenticate with, read from the token file or HASHASSIN_TOKEN
+fn client_token(token_file: Option<&str>) -> Result<Option<String>, Box<dyn Error>> {
+    Ok(match token_file {
+        Some(path) => Some(std::fs::read_to_string(path)?.trim().to_string()),
+        None => std::env::var("HASHASSIN_TOKEN").ok(),
+    })
 }
 
 fn main() -> Result<(), Box<dyn Error>> {
     tracing_subscriber::fmt::init();
     let cli = Cli::parse();
+    let token = client_token(cli.token_file.as_deref())?;
+    let client = |server: &str| ClientConfig::new(server).with_token(token.clone());
 
     match &cli.command {
         Commands::GenPasswords {
@@ -106,12 +321,13 @@ fn main() -> Result<(), Box<dyn Error>> {
             num,
             threads,
             out_file,
+            seed,
         } => {
             info!(
                 "Generating {} passwords of length {} using {} threads",
                 num, chars, threads
             );
-            let passwords = gen_passwords((*chars).into(), *num, *threads)?;
+            let passwords = gen_passwords((*chars).into(), *num, *threads, *seed)?;
             if let Some(file) = out_file {
                 let mut f = File::create(file)?;
                 for pwd in passwords {
@@ -133,31 +349,293 @@ fn main() -> Result<(), Box<dyn Error>> {
                 "Generating hashes from '{}' using {} algorithm",
                 in_file, algorithm
             );
-            gen_hashes(in_file, out_file, algorithm, *threads)?;
+            let (hooks, bar) = progress_hooks("hashes")?;
+            let result = gen_hashes(in_file, out_file, algorithm, *threads, &hooks);
+            bar.finish();
+            result?;
         }
         Commands::DumpHashes { in_file } => {
             dump_hashes(in_file)?;
         }
         Commands::GenRainbowTable {
             in_file,
+            chains,
+            table_seed,
             out_file,
             algorithm,
             num_links,
             threads,
+            min_len,
+            max_len,
+            compress,
+            dp_bits,
+            resume,
+            checkpoint_every,
         } => {
-            info!("Generating rainbow table from '{}'", in_file);
-            gen_rainbow_table(in_file, out_file, algorithm, *num_links, *threads)?;
+            let starts = match (in_file, chains) {
+                (Some(in_file), _) => {
+                    info!("Generating rainbow table from '{}'", in_file);
+                    ChainStarts::SeedFile(in_file)
+                }
+                (None, Some(count)) => {
+                    // Without an explicit seed, use the clock so tables don't collide
+                    let table_seed = match table_seed {
+                        Some(seed) => *seed,
+                        None => SystemTime::now().duration_since(UNIX_EPOCH)?.as_nanos() as u64,
+                    };
+                    info!(
+                        "Generating rainbow table with {} index-derived chains (table seed {})",
+                        count, table_seed
+                    );
+                    ChainStarts::Indexed {
+                        count: *count,
+                        table_seed,
+                    }
+                }
+                (None, None) => return Err("Either --in-file or --chains is required".into()),
+            };
+            let params = TableParams {
+                algorithm: algorithm.clone(),
+                num_links: *num_links,
+                threads: *threads,
+                len_range: min_len.zip(*max_len),
+                compress: *compress,
+                dp_bits: *dp_bits,
+                resume: *resume,
+                checkpoint_every: *checkpoint_every,
+            };
+            let (hooks, bar) = progress_hooks("chains")?;
+            let result = gen_rainbow_table(starts, out_file, &params, &hooks);
+            bar.finish();
+            result?;
         }
         Commands::DumpRainbowTable { in_file } => {
             dump_rainbow_table(in_file)?;
         }
+        Commands::Convert {
+            in_file,
+            out_file,
+            to_version,
+        } => {
+            let conversion = convert_file(in_file, out_file, *to_version)?;
+            println!(
+                "Converted {} '{}' from v{} to v{} ({} records) into '{}'",
+                conversion.kind,
+                in_file,
+                conversion.from,
+                conversion.to,
+                conversion.records,
+                out_file
+            );
+        }
+        Commands::Inspect {
+            in_file,
+            offset,
+            limit,
+            json,
+        } => {
+            let report = inspect(in_file, *offset, *limit)?;
+            if *json {
+                println!("{}", serde_json::to_string_pretty(&report)?);
+            } else {
+                println!("TYPE: {}", report.kind);
+                for (name, value) in report.header.iter().chain(&report.stats) {
+                    println!("{}: {}", name.to_uppercase(), value);
+                }
+                println!(
+                    "RECORDS: {}-{} of {}",
+                    report.offset.min(report.total_records),
+                    report.offset + report.records.len(),
+                    report.total_records
+                );
+                for record in &report.records {
+                    println!("{}", record.join("\t"));
+                }
+            }
+        }
+        Commands::MergeTables { in_files, out_file } => {
+            let paths: Vec<&str> = in_files.iter().map(String::as_str).collect();
+            let (read, written) = merge_tables(&paths, out_file)?;
+            println!(
+                "Merged {} tables into '{}': {} chains read, {} written",
+                paths.len(),
+                out_file,
+                read,
+                written
+            );
+        }
+        Commands::SplitTable {
+            in_file,
+            out_prefix,
+            chains_per_table,
+            prefix_chars,
+        } => {
+            let by = match (chains_per_table, prefix_chars) {
+                (Some(count), _) => SplitBy::ChainCount(*count),
+                (None, Some(chars)) => SplitBy::EndPrefix(*chars),
+                (None, None) => {
+                    return Err("Either --chains-per-table or --prefix-chars is required".into());
+                }
+            };
+            for path in split_table(in_file, out_prefix, by)? {
+                println!("{}", path);
+            }
+        }
+        Commands::VerifyTable { in_file, samples } => {
+            let report = verify_table(in_file, *samples)?;
+            if report.checksummed {
+                println!("CHECKSUMS: ok");
+            } else {
+                println!("CHECKSUMS: none (table predates v5)");
+            }
+            println!("CHAINS: {}", report.chains);
+            println!("SAMPLED: {}", report.sampled);
+            println!("MISMATCHES: {}", report.mismatches.len());
+            for (start, end, recomputed) in &report.mismatches {
+                println!(
+                    "{}\t{}\t{}",
+                    start,
+                    end,
+                    recomputed.as_deref().unwrap_or("NO END")
+                );
+            }
+            if !report.is_ok() {
+                return Err(format!(
+                    "{} of {} sampled chains do not match",
+                    report.mismatches.len(),
+                    report.sampled
+                )
+                .into());
+            }
+        }
+        Commands::AnalyzeTable { in_file } => {
+            let report = analyze_table(in_file)?;
+            println!("ALGORITHM: {}", report.header.algorithm);
+            println!(
+                "PASSWORD LENGTH: {}-{}",
+                report.header.min_len, report.header.max_len
+            );
+            println!("KEYSPACE: {:.4e}", report.keyspace);
+            println!("CHAINS: {}", report.chains);
+            println!("UNIQUE ENDPOINTS: {}", report.unique_endpoints);
+            println!("MERGE RATE: {:.2}%", report.merge_rate * 100.0);
+            println!("EXPECTED COVERAGE: {:.4e}", report.coverage);
+            println!(
+                "SUCCESS PROBABILITY: {:.4}%",
+                report.success_probability * 100.0
+            );
+            println!("HASHES PER LOOKUP: {:.0}", report.lookup_hashes);
+            println!("HASH RATE: {:.0}/s", report.hashes_per_sec);
+            println!("CRACK TIME PER HASH: {:.6}s", report.crack_secs_per_hash);
+        }
+        Commands::PlanTable {
+            min_len,
+            max_len,
+            success,
+            num_links,
+        } => {
+            let plan = plan_table(*min_len, *max_len, 95, *success, *num_links)?;
+            if !plan.target_reached {
+                println!("Target not reachable with a single table, best found:");
+            }
+            println!("KEYSPACE: {:.4e}", plan.keyspace);
+            println!("NUM LINKS: {}", plan.num_links);
+            println!("CHAINS: {}", plan.chains);
+            println!(
+                "SUCCESS PROBABILITY: {:.4}%",
+                plan.success_probability * 100.0
+            );
+            println!("HASHES PER LOOKUP: {:.0}", plan.lookup_hashes);
+        }
+        Commands::Benchmark {
+            algorithms,
+            threads,
+            lengths,
+            duration_ms,
+            num_links,
+            json,
+        } => {
+            // Default to every algorithm, and to one thread plus every core
+            let algorithms = if algorithms.is_empty() {
+                ALGORITHMS.iter().map(|algo| algo.to_string()).collect()
+            } else {
+                algorithms.clone()
+            };
+            let threads = if threads.is_empty() {
+                let cores = std::thread::available_parallelism().map_or(1, |n| n.get());
+                let mut threads = vec![1, cores];
+                threads.dedup();
+                threads
+            } else {
+                threads.clone()
+            };
+            let config = BenchConfig {
+                algorithms,
+                threads,
+                lengths: lengths.clone(),
+                duration: Duration::from_millis(*duration_ms),
+                num_links: *num_links,
+            };
+            info!("Running benchmark: {:?}", config);
+            let results = run_benchmark(&config)?;
+            if *json {
+                println!("{}", serde_json::to_string_pretty(&results)?);
+            } else {
+                println!(
+                    "{:<6} {:<10} {:>7} {:>6} {:>16}",
+                    "KIND", "ALGORITHM", "THREADS", "LENGTH", "RATE"
+                );
+                for r in &results {
+                    let unit = if r.kind == "hash" {
+                        "hashes/s"
+                    } else {
+                        "links/s"
+                    };
+                    println!(
+                        "{:<6} {:<10} {:>7} {:>6} {:>16.0} {}",
+                        r.kind, r.algorithm, r.threads, r.pwd_len, r.rate, unit
+                    );
+                }
+            }
+        }
+        Commands::Identify { hash, in_file } => {
+            let candidates = match (hash, in_file) {
+                (Some(hash), _) => identify(hash)?,
+                (None, Some(in_file)) => {
+                    let found = identify_file(in_file)?;
+                    println!("SOURCE: {}", found.source);
+                    println!("ENTRIES: {}", found.entries);
+                    if found.mismatched > 0 {
+                        println!("MISMATCHED: {} (mixed formats)", found.mismatched);
+                    }
+                    found.candidates
+                }
+                (None, None) => return Err("Either --hash or --in-file is required".into()),
+            };
+            println!(
+                "{:<14} {:>10} {:>9}  REASON",
+                "ALGORITHM", "CONFIDENCE", "SUPPORTED"
+            );
+            for c in candidates {
+                println!(
+                    "{:<14} {:>9.1}% {:>9}  {}",
+                    c.algorithm,
+                    c.confidence * 100.0,
+                    if c.supported { "yes" } else { "no" },
+                    c.reason
+                );
+            }
+        }
         Commands::Crack {
             in_file,
             hashes,
             out_file,
             threads,
         } => {
-            crack(in_file, hashes, out_file.as_deref(), *threads, None)?;
+            let (hooks, bar) = progress_hooks("hashes")?;
+            let result = crack(in_file, hashes, out_file.as_deref(), *threads, None, &hooks);
+            bar.finish();
+            result?;
         }
         Commands::Server {
             bind,
@@ -165,37 +643,180 @@ fn main() -> Result<(), Box<dyn Error>> {
             compute_threads,
             async_threads,
             cache_size,
+            data_dir,
+            max_upload_size,
+            max_crack_size,
+            auth_token_file,
         } => {
-            let address = format!("{}:{}", bind, port);
-            info!(
-                "Starting server at {} with compute_threads={}, async_threads={}, cache_size={:?}",
-                address, compute_threads, async_threads, cache_size
-            );
-            start_server(&address, *compute_threads, *async_threads, *cache_size)?;
+            let config = ServerConfig {
+                address: format!("{}:{}", bind, port),
+                compute_threads: *compute_threads,
+                async_threads: *async_threads,
+                cache_size: *cache_size,
+                data_dir: data_dir.clone(),
+                max_upload_size: *max_upload_size,
+                max_crack_size: *max_crack_size,
+                auth_token_file: auth_token_file.clone(),
+            };
+            info!("Starting server with {:?}", config);
+            start_server(&config)?;
         }
         Commands::ClientUpload {
             server,
             in_file,
             name,
+            replace,
         } => {
             info!("Uploading '{}' as '{}' to {}", in_file, name, server);
-            upload(server, in_file, name)?;
+            upload(&client(server), in_file, name, *replace)?;
         }
         Commands::ClientCrack {
             server,
             in_file,
             out_file,
+            tables,
         } => {
             info!("Submitting crack job to {}", server);
-            let result = client_crack(server, in_file)?;
+            // Results are written as they arrive, one line each, so the file is complete up
+            // to the last result even if the crack is interrupted
+            let mut out: Box<dyn Write> = match out_file {
+                Some(path) => Box::new(LineWriter::new(File::create(path)?)),
+                None => Box::new(std::io::stdout()),
+            };
+            let mut written = Ok(());
+            let summary = client_crack(&client(server), in_file, tables, |result| {
+                if written.is_ok() {
+                    let password = result.password.as_deref().unwrap_or("NOT FOUND");
+                    written = writeln!(out, "{}\t{}", result.hash, password);
+                }
+            })?;
+            written?;
+            info!("{}", summary);
+        }
+        Commands::ClientSubmit {
+            server,
+            in_file,
+            tables,
+        } => {
+            let job = client_submit(&client(server), in_file, tables)?;
+            println!("Submitted crack job {} to {}", job, server);
+        }
+        Commands::ClientStatus { server, job } => {
+            let result = match job {
+                Some(job) => client_job_status(&client(server), *job)?,
+                None => client_status(&client(server))?,
+            };
+            print!("{}", result);
+        }
+        Commands::ClientResult {
+            server,
+            job,
+            out_file,
+            wait,
+        } => {
+            // With --wait, poll until the job has finished
+            let result = loop {
+                match client_result(&client(server), *job) {
+                    Err(ClientError::Server {
+                        status: Status::Pending,
+                        ..
+                    }) if *wait => std::thread::sleep(Duration::from_secs(1)),
+                    result => break result?,
+                }
+            };
             if let Some(path) = out_file {
                 let mut f = File::create(path)?;
                 f.write_all(&result)?;
             } else {
-                println!("{}", String::from_utf8_lossy(&result));
+                print!("{}", String::from_utf8_lossy(&result));
             }
         }
+        Commands::ClientCancel { server, job } => {
+            client_cancel(&client(server), *job)?;
+            println!("Cancelling job {} on {}", job, server);
+        }
+        Commands::ClientList { server } => {
+            let tables = client_list(&client(server))?;
+            println!(
+                "{:<20} {:>7} {:<10} {:>8} {:>8} {:>10} {:>12}",
+                "NAME", "VERSION", "ALGORITHM", "LENGTH", "LINKS", "CHAINS", "SIZE"
+            );
+            for table in &tables {
+                println!(
+                    "{:<20} {:>7} {:<10} {:>8} {:>8} {:>10} {:>12}",
+                    table.name,
+                    table.version,
+                    table.algorithm,
+                    format!("{}-{}", table.min_len, table.max_len),
+                    table.num_links,
+                    table.chains,
+                    table.size
+                );
+            }
+            if tables.is_empty() {
+                println!("No tables");
+            }
+        }
+        Commands::ClientInfo { server, name } => {
+            let table = client_info(&client(server), name)?;
+            println!("NAME: {}", table.name);
+            println!("VERSION: {}", table.version);
+            println!("ALGORITHM: {}", table.algorithm);
+            println!("PASSWORD LENGTH: {}-{}", table.min_len, table.max_len);
+            println!("NUM LINKS: {}", table.num_links);
+            println!("CHAINS: {}", table.chains);
+            println!("SIZE: {}", table.size);
+        }
+        Commands::ClientDelete { server, name } => {
+            client_delete(&client(server), name)?;
+            println!("Deleted '{}' from {}", name, server);
+        }
+        Commands::ClientDownload {
+            server,
+            name,
+            out_file,
+        } => {
+            let size = client_download(&client(server), name, out_file)?;
+            println!("Downloaded '{}' ({} bytes) into '{}'", name, size, out_file);
+        }
     }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tokens_come_from_a_file_never_from_arguments() -> Result<(), Box<dyn Error>> {
+        let cli = Cli::try_parse_from([
+            "hashassin",
+            "client-list",
+            "--server",
+            "127.0.0.1:2025",
+            "--token-file",
+            "token.txt",
+        ])?;
+        assert_eq!(cli.token_file.as_deref(), Some("token.txt"));
+        assert!(
+            Cli::try_parse_from([
+                "hashassin",
+                "client-list",
+                "--server",
+                "127.0.0.1:2025",
+                "--token",
+                "s3cret",
+            ])
+            .is_err()
+        );
+
+        let path = std::env::temp_dir().join(format!("hashassin-token-{}", std::process::id()));
+        std::fs::write(&path, "  s3cret\n")?;
+        let token = client_token(path.to_str());
+        std::fs::remove_file(&path)?;
+        assert_eq!(token?.as_deref(), Some("s3cret"));
+        assert!(client_token(Some("/nonexistent/token")).is_err());
+        Ok(())
+    }
+}
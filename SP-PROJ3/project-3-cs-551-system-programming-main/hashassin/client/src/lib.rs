@@ -1,37 +1,537 @@
-use std::fs;
-use std::io::{Read, Write};
+use hashassin_core::{
+    CHALLENGE_LEN, RESPONSE_HEADER_LEN, UPLOAD_FLAG_REPLACE, chunk_crc, decode_response_header,
+    sign_challenge,
+};
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{Read, Seek, SeekFrom, Write};
 use std::net::TcpStream;
+use std::time::Duration;
 
-pub fn upload(server: &str, in_file: &str, name: &str) -> std::io::Result<()> {
-    let mut stream = TcpStream::connect(server)?;
-    let payload = fs::read(in_file)?;
-    let name_bytes = name.as_bytes();
+pub use hashassin_core::{Scope, Status, TableInfo};
+
+// Everything that can go wrong talking to a server
+#[derive(Debug)]
+pub enum ClientError {
+    Io(std::io::Error),
+    // The reply was not a response frame this client understands
+    Protocol(String),
+    // The server answered with a status other than OK
+    Server { status: Status, message: String },
+}
+
+impl fmt::Display for ClientError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ClientError::Io(e) => write!(f, "I/O error: {}", e),
+            ClientError::Protocol(message) => write!(f, "Protocol error: {}", message),
+            ClientError::Server { status, message } => {
+                write!(f, "Server replied {}: {}", status, message)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ClientError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ClientError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for ClientError {
+    fn from(e: std::io::Error) -> Self {
+        ClientError::Io(e)
+    }
+}
+
+pub type ClientResult<T> = Result<T, ClientError>;
+
+// The server to talk to and the token to authenticate with, if it wants one
+// Every function in this crate opens its own connection from this
+#[derive(Debug, Clone)]
+pub struct ClientConfig {
+    pub server: String,
+    pub token: Option<String>,
+}
+
+impl ClientConfig {
+    pub fn new(server: &str) -> ClientConfig {
+        ClientConfig {
+            server: server.to_string(),
+            token: None,
+        }
+    }
+
+    pub fn with_token(mut self, token: Option<String>) -> ClientConfig {
+        self.token = token;
+        self
+    }
+}
+
+// Proves to the server that this connection holds `token` and returns the token's scope
+// The token is not sent, only its HMAC of a nonce the server picks
+pub fn authenticate(stream: &mut TcpStream, token: &str) -> ClientResult<Scope> {
+    let challenge = exchange(stream, b"challg\x01")?; // 6 bytes and version
+    if challenge.len() != CHALLENGE_LEN {
+        return Err(ClientError::Protocol(format!(
+            "Challenge is {} bytes, expected {}",
+            challenge.len(),
+            CHALLENGE_LEN
+        )));
+    }
+    let mac = sign_challenge(token.as_bytes(), &challenge)
+        .map_err(|e| ClientError::Protocol(e.to_string()))?;
 
     let mut buffer = Vec::new();
-    buffer.extend_from_slice(b"upload"); // 6 bytes
+    buffer.extend_from_slice(b"auth\0\0"); // 6 bytes
     buffer.push(1); // version
-    buffer.push(name_bytes.len() as u8); // name length
-    buffer.extend_from_slice(name_bytes); // name
-    buffer.extend_from_slice(&(payload.len() as u64).to_be_bytes()); // payload size
-    buffer.extend_from_slice(&payload); // actual file
+    buffer.extend_from_slice(&mac); // HMAC of the challenge
+    let scope = exchange(stream, &buffer)?;
+    String::from_utf8_lossy(&scope)
+        .parse()
+        .map_err(ClientError::Protocol)
+}
+
+// Opens a connection, authenticated when the config has a token
+fn connect(config: &ClientConfig) -> ClientResult<TcpStream> {
+    let mut stream = TcpStream::connect(&config.server)?;
+    if let Some(token) = &config.token {
+        authenticate(&mut stream, token)?;
+    }
+    Ok(stream)
+}
+
+// Tables are uploaded in chunks of this size
+const UPLOAD_CHUNK_SIZE: usize = 4 * 1024 * 1024;
+// Failed attempts in a row after which an upload gives up, and the pause between them
+const UPLOAD_RETRIES: u32 = 5;
+const RETRY_DELAY: Duration = Duration::from_secs(1);
+
+// Where a chunked upload stands, kept across reconnects
+struct UploadState {
+    id: Option<u64>,
+    offset: u64,
+}
 
-    stream.write_all(&buffer)?;
+// Uploads a table, an existing table of the same name is only overwritten with `replace`
+// The table is streamed from disk in checksummed chunks. When the connection drops or a
+// chunk is refused the upload resumes from the offset the server has, giving up after
+// UPLOAD_RETRIES attempts in a row that make no progress
+pub fn upload(config: &ClientConfig, in_file: &str, name: &str, replace: bool) -> ClientResult<()> {
+    if name.len() > u8::MAX as usize {
+        return Err(ClientError::Protocol(format!(
+            "Table name '{}' is too long",
+            name
+        )));
+    }
+    let mut file = File::open(in_file)?;
+    let size = file.metadata()?.len();
+    let file_crc = file_checksum(&mut file)?;
+
+    let mut state = UploadState {
+        id: None,
+        offset: 0,
+    };
+    let mut failures = 0;
+    loop {
+        let before = state.offset;
+        let error =
+            match upload_attempt(config, &mut file, name, replace, size, file_crc, &mut state) {
+                Ok(()) => return Ok(()),
+                Err(e) => e,
+            };
+        let retryable = matches!(
+            error,
+            ClientError::Io(_)
+                | ClientError::Server {
+                    status: Status::ChunkMismatch,
+                    ..
+                }
+        );
+        failures = if state.offset > before {
+            1
+        } else {
+            failures + 1
+        };
+        if !retryable || failures > UPLOAD_RETRIES {
+            return Err(error);
+        }
+        std::thread::sleep(RETRY_DELAY * failures);
+    }
+}
+
+// One connection's worth of an upload: find the offset to start from, send the rest of the
+// chunks and complete the upload
+fn upload_attempt(
+    config: &ClientConfig,
+    file: &mut File,
+    name: &str,
+    replace: bool,
+    size: u64,
+    file_crc: u32,
+    state: &mut UploadState,
+) -> ClientResult<()> {
+    let mut stream = connect(config)?;
+
+    let resumed = match state.id {
+        Some(id) => match exchange(&mut stream, &upload_id_request(b"upoffs", id)) {
+            Ok(payload) => Some((id, read_u64s::<1>(&payload)?[0])),
+            // The server lost the upload, e.g. it restarted, so begin it again
+            Err(ClientError::Server {
+                status: Status::NotFound,
+                ..
+            }) => {
+                stream = connect(config)?;
+                None
+            }
+            Err(e) => return Err(e),
+        },
+        None => None,
+    };
+    let (id, offset) = match resumed {
+        Some(resumed) => resumed,
+        None => {
+            let mut buffer = Vec::new();
+            buffer.extend_from_slice(b"upbegn"); // 6 bytes
+            buffer.push(1); // version
+            buffer.push(if replace { UPLOAD_FLAG_REPLACE } else { 0 }); // flags
+            buffer.push(name.len() as u8); // name length
+            buffer.extend_from_slice(name.as_bytes()); // name
+            buffer.extend_from_slice(&size.to_be_bytes()); // table size
+            buffer.extend_from_slice(&file_crc.to_be_bytes()); // table checksum
+            let [id, offset] = read_u64s::<2>(&exchange(&mut stream, &buffer)?)?;
+            (id, offset)
+        }
+    };
+    state.id = Some(id);
+    state.offset = offset;
+
+    file.seek(SeekFrom::Start(state.offset))?;
+    let mut chunk = Vec::with_capacity(UPLOAD_CHUNK_SIZE);
+    while state.offset < size {
+        chunk.clear();
+        Read::by_ref(file)
+            .take(UPLOAD_CHUNK_SIZE as u64)
+            .read_to_end(&mut chunk)?;
+        if chunk.is_empty() {
+            return Err(ClientError::Protocol(format!(
+                "Table file shrank to {} bytes during the upload",
+                state.offset
+            )));
+        }
+
+        let mut buffer = Vec::with_capacity(chunk.len() + 32);
+        buffer.extend_from_slice(b"upchnk"); // 6 bytes
+        buffer.push(1); // version
+        buffer.extend_from_slice(&id.to_be_bytes()); // upload id
+        buffer.extend_from_slice(&state.offset.to_be_bytes()); // offset of the chunk
+        buffer.extend_from_slice(&(chunk.len() as u32).to_be_bytes()); // chunk length
+        buffer.extend_from_slice(&chunk_crc(&chunk).to_be_bytes()); // chunk checksum
+        buffer.extend_from_slice(&chunk); // chunk
+
+        let next = read_u64s::<1>(&exchange(&mut stream, &buffer)?)?[0];
+        if next != state.offset + chunk.len() as u64 {
+            // The server is somewhere else, continue from where it is
+            file.seek(SeekFrom::Start(next))?;
+        }
+        state.offset = next;
+    }
+
+    exchange(&mut stream, &upload_id_request(b"updone", id))?;
     Ok(())
 }
 
-pub fn crack(server: &str, in_file: &str) -> std::io::Result<Vec<u8>> {
-    let mut stream = TcpStream::connect(server)?;
+fn upload_id_request(command: &[u8; 6], id: u64) -> Vec<u8> {
+    let mut buffer = Vec::new();
+    buffer.extend_from_slice(command); // 6 bytes
+    buffer.push(1); // version
+    buffer.extend_from_slice(&id.to_be_bytes()); // upload id
+    buffer
+}
+
+// CRC32 of a whole file, read in chunks, leaving the file at its end
+fn file_checksum(file: &mut File) -> std::io::Result<u32> {
+    let mut hasher = crc32fast::Hasher::new();
+    let mut buffer = vec![0u8; UPLOAD_CHUNK_SIZE];
+    loop {
+        let n = file.read(&mut buffer)?;
+        if n == 0 {
+            return Ok(hasher.finalize());
+        }
+        hasher.update(&buffer[..n]);
+    }
+}
+
+// Reads the big-endian u64s upload and submit commands answer with
+fn read_u64s<const N: usize>(payload: &[u8]) -> ClientResult<[u64; N]> {
+    if payload.len() != N * 8 {
+        return Err(ClientError::Protocol(format!(
+            "Expected {} bytes of offsets, got {}",
+            N * 8,
+            payload.len()
+        )));
+    }
+    let mut values = [0u64; N];
+    for (value, bytes) in values.iter_mut().zip(payload.chunks_exact(8)) {
+        let mut word = [0u8; 8];
+        word.copy_from_slice(bytes);
+        *value = u64::from_be_bytes(word);
+    }
+    Ok(values)
+}
+
+// One hash of a streamed crack, `password` is None when no table covers it
+#[derive(Debug, Clone, PartialEq)]
+pub struct CrackResult {
+    pub hash: String,
+    pub password: Option<String>,
+}
+
+// Cracks a hash file, calling `on_result` for every hash as soon as the server has cracked
+// it or every table has missed it, so results arrive in that order rather than file order
+// Returns the server's summary once every hash is done
+// With no table names the server tries every table that fits the hashes
+pub fn crack(
+    config: &ClientConfig,
+    in_file: &str,
+    tables: &[String],
+    mut on_result: impl FnMut(CrackResult),
+) -> ClientResult<String> {
+    let mut stream = connect(config)?;
+    // 6 bytes: includes null terminator, then the version
+    let buffer = crack_request(b"crack\0", 3, in_file, tables)?;
+    let mut frame = send(&mut stream, &buffer)?;
+    loop {
+        match frame {
+            (Status::More, payload) => {
+                let line = String::from_utf8_lossy(&payload);
+                let (hash, password) = line.split_once('\t').ok_or_else(|| {
+                    ClientError::Protocol(format!("Malformed crack result: '{}'", line))
+                })?;
+                on_result(CrackResult {
+                    hash: hash.to_string(),
+                    password: (password != "NOT FOUND").then(|| password.to_string()),
+                });
+            }
+            (Status::Ok, payload) => return Ok(String::from_utf8_lossy(&payload).to_string()),
+            (status, payload) => {
+                return Err(ClientError::Server {
+                    status,
+                    message: String::from_utf8_lossy(&payload).to_string(),
+                });
+            }
+        }
+        frame = read_frame(&mut stream)?;
+    }
+}
+
+// Starts a crack job on the server and returns its id without waiting for it
+pub fn submit(config: &ClientConfig, in_file: &str, tables: &[String]) -> ClientResult<u64> {
+    let mut stream = connect(config)?;
+    let buffer = crack_request(b"submit", 1, in_file, tables)?;
+    Ok(read_u64s::<1>(&exchange(&mut stream, &buffer)?)?[0])
+}
+
+// One job's status line followed by the hash and password of everything it cracked so far
+pub fn job_status(config: &ClientConfig, job: u64) -> ClientResult<String> {
+    let payload = job_request(config, b"status", 2, job)?;
+    Ok(String::from_utf8_lossy(&payload).to_string())
+}
+
+// The output of a finished job, a Pending error while it still runs
+pub fn result(config: &ClientConfig, job: u64) -> ClientResult<Vec<u8>> {
+    job_request(config, b"result", 1, job)
+}
+
+// Asks a job to stop, it keeps what it cracked so far
+pub fn cancel(config: &ClientConfig, job: u64) -> ClientResult<()> {
+    job_request(config, b"cancel", 1, job)?;
+    Ok(())
+}
+
+fn job_request(
+    config: &ClientConfig,
+    command: &[u8; 6],
+    version: u8,
+    job: u64,
+) -> ClientResult<Vec<u8>> {
+    let mut stream = connect(config)?;
+    let mut buffer = Vec::new();
+    buffer.extend_from_slice(command); // 6 bytes
+    buffer.push(version); // version
+    buffer.extend_from_slice(&job.to_be_bytes()); // job id
+    exchange(&mut stream, &buffer)
+}
+
+// command | version | table_count u8 | [name_len u8 | name]* | payload_len u64 | hash file
+fn crack_request(
+    command: &[u8; 6],
+    version: u8,
+    in_file: &str,
+    tables: &[String],
+) -> ClientResult<Vec<u8>> {
     let payload = fs::read(in_file)?;
+    if tables.len() > u8::MAX as usize {
+        return Err(ClientError::Protocol("Too many table names".to_string()));
+    }
 
     let mut buffer = Vec::new();
-    buffer.extend_from_slice(b"crack\0"); // 6 bytes: includes null terminator
-    buffer.push(1); // version
+    buffer.extend_from_slice(command); // 6 bytes
+    buffer.push(version); // version
+    buffer.push(tables.len() as u8); // number of table names
+    for name in tables {
+        if name.len() > u8::MAX as usize {
+            return Err(ClientError::Protocol(format!(
+                "Table name '{}' is too long",
+                name
+            )));
+        }
+        buffer.push(name.len() as u8); // name length
+        buffer.extend_from_slice(name.as_bytes()); // name
+    }
     buffer.extend_from_slice(&(payload.len() as u64).to_be_bytes()); // payload size
     buffer.extend_from_slice(&payload); // hash file
+    Ok(buffer)
+}
+
+pub fn status(config: &ClientConfig) -> ClientResult<String> {
+    let mut stream = connect(config)?;
+
+    let mut buffer = Vec::new();
+    buffer.extend_from_slice(b"status"); // 6 bytes
+    buffer.push(1); // version
+
+    let payload = exchange(&mut stream, &buffer)?;
+    Ok(String::from_utf8_lossy(&payload).to_string())
+}
+
+// Every table on the server, sorted by name
+pub fn list(config: &ClientConfig) -> ClientResult<Vec<TableInfo>> {
+    let mut stream = connect(config)?;
+    let payload = exchange(&mut stream, b"list\0\0\x01")?; // 6 bytes and version
+    String::from_utf8_lossy(&payload)
+        .lines()
+        .map(parse_info)
+        .collect()
+}
+
+pub fn info(config: &ClientConfig, name: &str) -> ClientResult<TableInfo> {
+    let payload = named_request(config, b"info\0\0", name)?;
+    parse_info(String::from_utf8_lossy(&payload).trim_end())
+}
 
-    stream.write_all(&buffer)?;
+pub fn delete(config: &ClientConfig, name: &str) -> ClientResult<()> {
+    named_request(config, b"delete", name)?;
+    Ok(())
+}
+
+// Saves a table from the server to `out_file`, returns its size in bytes
+// The table is copied from the connection into `out_file.tmp` as it arrives and renamed
+// into place once it is complete, so it is never held in memory or left half written
+pub fn download(config: &ClientConfig, name: &str, out_file: &str) -> ClientResult<u64> {
+    let mut stream = connect(config)?;
+    stream.write_all(&named_command(b"downld", name)?)?;
+    let (status, len) = read_header(&mut stream)?;
+    if status != Status::Ok {
+        let payload = read_payload(&mut stream, len)?;
+        return Err(ClientError::Server {
+            status,
+            message: String::from_utf8_lossy(&payload).to_string(),
+        });
+    }
+
+    let tmp = format!("{}.tmp", out_file);
+    let copied = File::create(&tmp)
+        .and_then(|mut file| std::io::copy(&mut (&mut stream).take(len), &mut file));
+    match copied {
+        Ok(copied) if copied == len => {}
+        Ok(copied) => {
+            let _ = fs::remove_file(&tmp);
+            return Err(ClientError::Protocol(format!(
+                "Response ended after {} of {} bytes",
+                copied, len
+            )));
+        }
+        Err(e) => {
+            let _ = fs::remove_file(&tmp);
+            return Err(e.into());
+        }
+    }
+    fs::rename(&tmp, out_file)?;
+    Ok(len)
+}
+
+// Sends a command whose only argument is a table name
+fn named_request(config: &ClientConfig, command: &[u8; 6], name: &str) -> ClientResult<Vec<u8>> {
+    let mut stream = connect(config)?;
+    exchange(&mut stream, &named_command(command, name)?)
+}
+
+fn named_command(command: &[u8; 6], name: &str) -> ClientResult<Vec<u8>> {
+    if name.len() > u8::MAX as usize {
+        return Err(ClientError::Protocol(format!(
+            "Table name '{}' is too long",
+            name
+        )));
+    }
+
+    let mut buffer = Vec::new();
+    buffer.extend_from_slice(command); // 6 bytes
+    buffer.push(1); // version
+    buffer.push(name.len() as u8); // name length
+    buffer.extend_from_slice(name.as_bytes()); // name
+    Ok(buffer)
+}
+
+fn parse_info(line: &str) -> ClientResult<TableInfo> {
+    TableInfo::from_line(line).map_err(|e| ClientError::Protocol(e.to_string()))
+}
+
+// Sends a request and reads the response, turning any status but OK into an error
+fn exchange(stream: &mut TcpStream, request: &[u8]) -> ClientResult<Vec<u8>> {
+    match send(stream, request)? {
+        (Status::Ok, payload) => Ok(payload),
+        (status, payload) => Err(ClientError::Server {
+            status,
+            message: String::from_utf8_lossy(&payload).to_string(),
+        }),
+    }
+}
+
+// Sends a request and reads the first response frame
+// The server may refuse a request before reading all of it, so even when sending fails
+// its response is read, it says why
+fn send(stream: &mut TcpStream, request: &[u8]) -> ClientResult<(Status, Vec<u8>)> {
+    match stream.write_all(request) {
+        Ok(()) => read_frame(stream),
+        Err(e) => read_frame(stream).map_err(|_| ClientError::Io(e)),
+    }
+}
+
+// Reads one response frame, whatever its status
+fn read_frame(stream: &mut TcpStream) -> ClientResult<(Status, Vec<u8>)> {
+    let (status, len) = read_header(stream)?;
+    Ok((status, read_payload(stream, len)?))
+}
+
+fn read_header(stream: &mut TcpStream) -> ClientResult<(Status, u64)> {
+    let mut header = [0u8; RESPONSE_HEADER_LEN];
+    stream.read_exact(&mut header)?;
+    decode_response_header(&header).map_err(|e| ClientError::Protocol(e.to_string()))
+}
 
-    let mut response = Vec::new();
-    stream.read_to_end(&mut response)?;
-    Ok(response)
+fn read_payload(stream: &mut TcpStream, len: u64) -> ClientResult<Vec<u8>> {
+    let mut payload = Vec::new();
+    stream.take(len).read_to_end(&mut payload)?;
+    if payload.len() as u64 != len {
+        return Err(ClientError::Protocol(format!(
+            "Response ended after {} of {} bytes",
+            payload.len(),
+            len
+        )));
+    }
+    Ok(payload)
 }
@@ -0,0 +1,258 @@
+// Coverage and success estimates for rainbow tables, plus a planner for new ones
+//
+// Every chain uses the same reduction, so two chains that reach the same password
+// merge for good. The estimates follow the usual random-function model: if column i
+// holds m_i distinct passwords, column i+1 holds about N * (1 - e^(-m_i / N)).
+
+use crate::{TableHeader, compute_hash, keyspace_size};
+use std::collections::HashSet;
+use std::time::{Duration, Instant};
+
+// Longest chain the planner will suggest, anything longer makes lookups impractical
+const MAX_PLAN_LINKS: usize = 1_000_000;
+
+// What analyze_table found out about a table
+#[derive(Debug, Clone)]
+pub struct TableAnalysis {
+    pub header: TableHeader,
+    pub keyspace: f64,
+    pub chains: usize,
+    pub unique_endpoints: usize,
+    // Fraction of chains whose end point is shared with another chain
+    pub merge_rate: f64,
+    // Expected number of distinct passwords the table covers
+    pub coverage: f64,
+    pub success_probability: f64,
+    // Expected hash computations to look up one hash that is not in the table
+    pub lookup_hashes: f64,
+    pub hashes_per_sec: f64,
+    pub crack_secs_per_hash: f64,
+}
+
+// Chain count and length suggested by plan_table
+#[derive(Debug, Clone)]
+pub struct TablePlan {
+    pub keyspace: f64,
+    pub chains: u64,
+    pub num_links: usize,
+    pub success_probability: f64,
+    // True when the target could not be reached and this is the best found
+    pub target_reached: bool,
+    pub lookup_hashes: f64,
+}
+
+// Expected distinct passwords covered by `chains` chains of `links` links over a keyspace
+pub fn estimate_coverage(keyspace: f64, chains: f64, links: usize) -> f64 {
+    let mut column = chains.min(keyspace);
+    let mut covered = column;
+    for _ in 0..links {
+        column = keyspace * -(-column / keyspace).exp_m1();
+        covered += column;
+    }
+    covered
+}
+
+// Chance that a random password of the keyspace is covered, allowing for overlap between columns
+pub fn estimate_success(keyspace: f64, chains: f64, links: usize) -> f64 {
+    -(-estimate_coverage(keyspace, chains, links) / keyspace).exp_m1()
+}
+
+// Expected hash computations for a lookup that misses, which is the worst case
+pub fn estimate_lookup_hashes(links: usize, dp_bits: Option<u8>) -> f64 {
+    match dp_bits {
+        // One walk forward to the next distinguished point
+        Some(bits) => (2f64.powi(bits as i32)).min(links as f64),
+        // Every column is tried, each walking to the end of the chain
+        None => (links as f64) * (links as f64 + 1.0) / 2.0,
+    }
+}
+
+// Measures how many hashes per second one thread manages for an algorithm
+pub fn measure_hash_rate(
+    algorithm: &str,
+    pwd_len: usize,
+    budget: Duration,
+) -> Result<f64, Box<dyn std::error::Error>> {
+    let pwd = "a".repeat(pwd_len.max(1));
+    let started = Instant::now();
+    let mut done = 0u64;
+    while started.elapsed() < budget || done == 0 {
+        compute_hash(&pwd, algorithm)?;
+        done += 1;
+    }
+    Ok(done as f64 / started.elapsed().as_secs_f64())
+}
+
+// Reads a table and estimates how well it covers its keyspace
+pub fn analyze_table(in_file: &str) -> Result<TableAnalysis, Box<dyn std::error::Error>> {
+    let data = std::fs::read(in_file)?;
+    let (header, pos) = TableHeader::parse(&data)?;
+    let chains = header.read_chains(&data, pos)?;
+
+    let keyspace = keyspace_f64(header.min_len, header.max_len, header.charset_size);
+    let unique_endpoints = chains
+        .iter()
+        .map(|(_, end)| end.as_str())
+        .collect::<HashSet<_>>()
+        .len();
+    let merge_rate = if chains.is_empty() {
+        0.0
+    } else {
+        1.0 - unique_endpoints as f64 / chains.len() as f64
+    };
+
+    // Distinguished point chains average about 2^bits links
+    let dp_bits = header.distinguished().then_some(header.dp_bits);
+    let links = match dp_bits {
+        Some(bits) => (2f64.powi(bits as i32)).min(header.num_links as f64) as usize,
+        None => header.num_links,
+    };
+    // Merges are part of the model, so start from every chain rather than the unique ends
+    let coverage = estimate_coverage(keyspace, chains.len() as f64, links).min(keyspace);
+    let success_probability = estimate_success(keyspace, chains.len() as f64, links);
+
+    let lookup_hashes = estimate_lookup_hashes(header.num_links, dp_bits);
+    let hashes_per_sec = measure_hash_rate(
+        &header.algorithm,
+        header.max_len,
+        Duration::from_millis(200),
+    )?;
+
+    Ok(TableAnalysis {
+        keyspace,
+        chains: chains.len(),
+        unique_endpoints,
+        merge_rate,
+        coverage,
+        success_probability,
+        lookup_hashes,
+        hashes_per_sec,
+        crack_secs_per_hash: lookup_hashes / hashes_per_sec,
+        header,
+    })
+}
+
+// Recommends a chain count and length that reach `target` success over a keyspace
+// When num_links is given only the chain count is searched, otherwise chains and links
+// are balanced so storage and lookup work grow together (chains = links^2)
+pub fn plan_table(
+    min_len: usize,
+    max_len: usize,
+    charset_size: u128,
+    target: f64,
+    num_links: Option<usize>,
+) -> Result<TablePlan, Box<dyn std::error::Error>> {
+    if target <= 0.0 || target >= 1.0 {
+        return Err("Target success rate must be between 0 and 1".into());
+    }
+    if min_len == 0 || min_len > max_len {
+        return Err(format!("Invalid password length range {}..={}", min_len, max_len).into());
+    }
+    let keyspace = keyspace_f64(min_len, max_len, charset_size);
+
+    let plan = |chains: f64, links: usize, reached: bool| TablePlan {
+        keyspace,
+        chains: chains as u64,
+        num_links: links,
+        success_probability: estimate_success(keyspace, chains, links),
+        target_reached: reached,
+        lookup_hashes: estimate_lookup_hashes(links, None),
+    };
+
+    match num_links {
+        Some(links) => {
+            // Double the chain count until we overshoot, then binary search back
+            let mut high = 1.0;
+            while estimate_success(keyspace, high, links) < target {
+                if high >= keyspace {
+                    return Ok(plan(keyspace, links, false));
+                }
+                high *= 2.0;
+            }
+            let mut low = (high / 2.0).floor();
+            while high - low > 1.0 {
+                let mid = ((low + high) / 2.0).floor();
+                if estimate_success(keyspace, mid, links) >= target {
+                    high = mid;
+                } else {
+                    low = mid;
+                }
+            }
+            Ok(plan(high, links, true))
+        }
+        None => {
+            // Grow the balanced table until it reaches the target or gets impractical
+            let mut links = 1usize;
+            let mut best = plan(1.0, 1, false);
+            while links <= MAX_PLAN_LINKS {
+                let chains = ((links as f64) * (links as f64)).min(keyspace);
+                let success = estimate_success(keyspace, chains, links);
+                if success >= target {
+                    return Ok(plan(chains, links, true));
+                }
+                best = plan(chains, links, false);
+                links = (links + 1).max(links + links / 8);
+            }
+            Ok(best)
+        }
+    }
+}
+
+// Keyspace size as a float, which is plenty for probability estimates
+fn keyspace_f64(min_len: usize, max_len: usize, charset_size: u128) -> f64 {
+    keyspace_size(min_len, max_len, charset_size)
+        .to_string()
+        .parse::<f64>()
+        .unwrap_or(f64::INFINITY)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    type TestResult = Result<(), Box<dyn std::error::Error>>;
+
+    #[test]
+    fn coverage_rises_with_chains_and_success_stays_below_one() {
+        let keyspace = 95f64.powi(4);
+        let mut last = (0.0, 0.0);
+        for chains in [1.0, 10.0, 1e3, 1e5, 1e7, keyspace] {
+            let coverage = estimate_coverage(keyspace, chains, 100);
+            let success = estimate_success(keyspace, chains, 100);
+            assert!(
+                coverage > last.0,
+                "coverage did not rise at {} chains",
+                chains
+            );
+            assert!(
+                success > last.1,
+                "success did not rise at {} chains",
+                chains
+            );
+            assert!(success <= 1.0);
+            last = (coverage, success);
+        }
+        assert!(last.1 > 0.99);
+        // Chains beyond the keyspace add nothing new
+        assert_eq!(estimate_coverage(keyspace, 1e12, 100), last.0);
+    }
+
+    #[test]
+    fn plans_reach_their_target_with_as_few_chains_as_needed() -> TestResult {
+        let keyspace = 26f64.powi(4);
+        let plan = plan_table(4, 4, 26, 0.9, Some(100))?;
+        assert!(plan.target_reached);
+        assert_eq!(plan.keyspace, keyspace);
+        assert!(plan.success_probability >= 0.9);
+        assert!(estimate_success(keyspace, plan.chains as f64 - 1.0, 100) < 0.9);
+
+        let balanced = plan_table(4, 4, 26, 0.9, None)?;
+        assert!(balanced.target_reached);
+        assert!(balanced.success_probability >= 0.9);
+        assert!(balanced.success_probability <= 1.0);
+
+        assert!(plan_table(4, 4, 26, 1.0, None).is_err());
+        assert!(plan_table(5, 4, 26, 0.5, None).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,240 @@
+// Token authentication between hashassin clients and servers
+//
+// A server started with a token file only runs commands on connections that have proven
+// they hold one of its tokens. The token itself never crosses the wire: the client asks
+// for a challenge, a fresh random nonce, and answers with HMAC-SHA256(token, nonce).
+// Each token has a scope, read for cracking and reading tables and jobs, or admin for
+// everything including uploading and deleting tables.
+
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::fmt;
+use std::str::FromStr;
+
+type HmacSha256 = Hmac<Sha256>;
+
+// Length of a challenge nonce and of the HMAC answering it
+pub const CHALLENGE_LEN: usize = 32;
+pub const MAC_LEN: usize = 32;
+
+// What a token allows, admin includes everything read does
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Scope {
+    Read,
+    Admin,
+}
+
+impl Scope {
+    pub fn allows(self, needed: Scope) -> bool {
+        self >= needed
+    }
+}
+
+impl FromStr for Scope {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Scope, String> {
+        match name {
+            "read" => Ok(Scope::Read),
+            "admin" => Ok(Scope::Admin),
+            _ => Err(format!("Unknown token scope '{}', use read or admin", name)),
+        }
+    }
+}
+
+impl fmt::Display for Scope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Scope::Read => "read",
+            Scope::Admin => "admin",
+        })
+    }
+}
+
+// A token a server accepts
+#[derive(Clone)]
+pub struct AuthToken {
+    pub scope: Scope,
+    secret: Vec<u8>,
+}
+
+// Reads a token file, one `scope token` pair per line, e.g. `admin 5f1c...`
+// Blank lines and lines starting with # are skipped
+pub fn load_tokens(path: &str) -> Result<Vec<AuthToken>, Box<dyn std::error::Error>> {
+    let text = std::fs::read_to_string(path)?;
+    let mut tokens = Vec::new();
+    for (number, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (scope, secret) = line
+            .split_once(char::is_whitespace)
+            .ok_or_else(|| format!("{}:{}: expected 'scope token'", path, number + 1))?;
+        let scope = scope
+            .parse()
+            .map_err(|e| format!("{}:{}: {}", path, number + 1, e))?;
+        tokens.push(AuthToken {
+            scope,
+            secret: secret.trim().as_bytes().to_vec(),
+        });
+    }
+    if tokens.is_empty() {
+        return Err(format!("No tokens in '{}'", path).into());
+    }
+    Ok(tokens)
+}
+
+// A fresh nonce for a client to sign
+pub fn new_challenge() -> [u8; CHALLENGE_LEN] {
+    rand::random()
+}
+
+// The answer to a challenge: HMAC-SHA256 keyed with the token
+pub fn sign_challenge(
+    token: &[u8],
+    challenge: &[u8],
+) -> Result<[u8; MAC_LEN], Box<dyn std::error::Error>> {
+    let mut mac = HmacSha256::new_from_slice(token).map_err(|e| e.to_string())?;
+    mac.update(challenge);
+    Ok(mac.finalize().into_bytes().into())
+}
+
+// The index and scope of the token an answer was signed with, compared in constant time
+// If several tokens share a secret the one with the widest scope wins
+pub fn verify_response(
+    tokens: &[AuthToken],
+    challenge: &[u8],
+    response: &[u8],
+) -> Option<(usize, Scope)> {
+    tokens
+        .iter()
+        .enumerate()
+        .filter(|(_, token)| {
+            HmacSha256::new_from_slice(&token.secret).is_ok_and(|mut mac| {
+                mac.update(challenge);
+                mac.verify_slice(response).is_ok()
+            })
+        })
+        .max_by_key(|(_, token)| token.scope)
+        .map(|(index, token)| (index, token.scope))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn token(scope: Scope, secret: &str) -> AuthToken {
+        AuthToken {
+            scope,
+            secret: secret.as_bytes().to_vec(),
+        }
+    }
+
+    // Writes a token file unique to the test and loads it back
+    fn load(name: &str, text: &str) -> Result<Vec<AuthToken>, Box<dyn std::error::Error>> {
+        let path =
+            std::env::temp_dir().join(format!("hashassin-tokens-{}-{}", std::process::id(), name));
+        std::fs::write(&path, text)?;
+        let path = path.to_string_lossy().to_string();
+        let tokens = load_tokens(&path);
+        std::fs::remove_file(&path)?;
+        tokens
+    }
+
+    #[test]
+    fn signature_matches_hmac_sha256() -> Result<(), Box<dyn std::error::Error>> {
+        // RFC 4231 test case 2
+        let mac = sign_challenge(b"Jefe", b"what do ya want for nothing?")?;
+        assert_eq!(
+            hex::encode(mac),
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn only_the_right_token_and_challenge_verify() -> Result<(), Box<dyn std::error::Error>> {
+        let tokens = vec![token(Scope::Admin, "admin"), token(Scope::Read, "reader")];
+        let challenge = new_challenge();
+        let response = sign_challenge(b"reader", &challenge)?;
+        assert_eq!(
+            verify_response(&tokens, &challenge, &response),
+            Some((1, Scope::Read))
+        );
+
+        let wrong_token = sign_challenge(b"stranger", &challenge)?;
+        assert_eq!(verify_response(&tokens, &challenge, &wrong_token), None);
+
+        let other_challenge = new_challenge();
+        assert_ne!(challenge, other_challenge);
+        assert_eq!(verify_response(&tokens, &other_challenge, &response), None);
+
+        let mut tampered = response;
+        tampered[MAC_LEN - 1] ^= 1;
+        assert_eq!(verify_response(&tokens, &challenge, &tampered), None);
+        assert_eq!(
+            verify_response(&tokens, &challenge, &response[..MAC_LEN - 1]),
+            None
+        );
+        assert_eq!(verify_response(&[], &challenge, &response), None);
+        Ok(())
+    }
+
+    #[test]
+    fn shared_secret_gets_the_widest_scope() -> Result<(), Box<dyn std::error::Error>> {
+        let tokens = vec![
+            token(Scope::Read, "shared"),
+            token(Scope::Admin, "shared"),
+            token(Scope::Read, "shared"),
+        ];
+        let challenge = new_challenge();
+        let response = sign_challenge(b"shared", &challenge)?;
+        assert_eq!(
+            verify_response(&tokens, &challenge, &response),
+            Some((1, Scope::Admin))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn admin_allows_everything_read_does() -> Result<(), Box<dyn std::error::Error>> {
+        assert!(Scope::Admin > Scope::Read);
+        assert!(Scope::Admin.allows(Scope::Admin));
+        assert!(Scope::Admin.allows(Scope::Read));
+        assert!(Scope::Read.allows(Scope::Read));
+        assert!(!Scope::Read.allows(Scope::Admin));
+
+        for scope in [Scope::Read, Scope::Admin] {
+            assert_eq!(scope.to_string().parse::<Scope>()?, scope);
+        }
+        assert!("Admin".parse::<Scope>().is_err());
+        assert!("write".parse::<Scope>().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn token_files_parse_scopes_and_skip_comments() -> Result<(), Box<dyn std::error::Error>> {
+        let tokens = load(
+            "good",
+            "# server tokens\n\nadmin  s3cret admin\n  read\tr3ad-only  \n",
+        )?;
+        assert_eq!(tokens.len(), 2);
+        assert_eq!(tokens[0].scope, Scope::Admin);
+        assert_eq!(tokens[0].secret, b"s3cret admin");
+        assert_eq!(tokens[1].scope, Scope::Read);
+        assert_eq!(tokens[1].secret, b"r3ad-only");
+
+        let error = load("scope", "read ok\nwrite nope\n")
+            .err()
+            .ok_or("bad scope loaded")?;
+        assert!(
+            error
+                .to_string()
+                .contains(":2: Unknown token scope 'write'")
+        );
+        assert!(load("bare", "admin\n").is_err());
+        assert!(load("empty", "# nothing here\n\n").is_err());
+        Ok(())
+    }
+}
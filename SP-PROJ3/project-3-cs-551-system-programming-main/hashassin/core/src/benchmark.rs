@@ -0,0 +1,154 @@
+// Throughput benchmarks for hashing and chain walking
+//
+// Each measurement runs `threads` workers for a fixed time and counts finished work.
+// Hash runs count single hashes, chain runs count hash-reduce links the way crack walks
+// them, so the results can be used to size tables for the machine.
+
+use crate::{compute_hash, walk_chain};
+use serde::Serialize;
+use std::thread;
+use std::time::{Duration, Instant};
+
+// What to measure, every combination of algorithm, thread count and length is run
+#[derive(Debug, Clone)]
+pub struct BenchConfig {
+    pub algorithms: Vec<String>,
+    pub threads: Vec<usize>,
+    pub lengths: Vec<usize>,
+    // Time spent on each measurement
+    pub duration: Duration,
+    // Links per chain for the chain walk measurements
+    pub num_links: usize,
+}
+
+// One measurement, `rate` is hashes per second for "hash" and links per second for "chain"
+#[derive(Debug, Clone, Serialize)]
+pub struct BenchResult {
+    pub kind: &'static str,
+    pub algorithm: String,
+    pub threads: usize,
+    pub pwd_len: usize,
+    pub rate: f64,
+}
+
+// Runs every hash and chain walk measurement in the config
+pub fn run_benchmark(config: &BenchConfig) -> Result<Vec<BenchResult>, Box<dyn std::error::Error>> {
+    if config.num_links == 0 {
+        return Err("Number of links must be greater than zero".into());
+    }
+    let mut results = Vec::new();
+    for algorithm in &config.algorithms {
+        // Fail on unknown algorithms before spending any time measuring
+        compute_hash("", algorithm)?;
+        for &pwd_len in &config.lengths {
+            if pwd_len == 0 || pwd_len > u8::MAX as usize {
+                return Err(format!("Invalid password length {}", pwd_len).into());
+            }
+            for &threads in &config.threads {
+                let threads = threads.max(1);
+                for kind in ["hash", "chain"] {
+                    let rate = measure(kind, algorithm, threads, pwd_len, config)?;
+                    results.push(BenchResult {
+                        kind,
+                        algorithm: algorithm.clone(),
+                        threads,
+                        pwd_len,
+                        rate,
+                    });
+                }
+            }
+        }
+    }
+    Ok(results)
+}
+
+// Runs one measurement and returns the combined rate of all threads
+fn measure(
+    kind: &'static str,
+    algorithm: &str,
+    threads: usize,
+    pwd_len: usize,
+    config: &BenchConfig,
+) -> Result<f64, Box<dyn std::error::Error>> {
+    let allowed: Vec<u8> = (32u8..=126u8).collect();
+    let started = Instant::now();
+    let deadline = started + config.duration;
+
+    let mut handles = Vec::new();
+    for worker in 0..threads {
+        let algo = algorithm.to_string();
+        let allowed = allowed.clone();
+        let num_links = config.num_links;
+
+        handles.push(thread::spawn(move || {
+            // Vary the last character so every worker hashes different passwords
+            let mut pwd = vec![b'a'; pwd_len];
+            let mut done = 0u64;
+            let mut round = worker as u64;
+            while Instant::now() < deadline || done == 0 {
+                pwd[pwd_len - 1] = allowed[(round % allowed.len() as u64) as usize];
+                round += threads as u64;
+                let start = String::from_utf8_lossy(&pwd).to_string();
+                match kind {
+                    "hash" => {
+                        if compute_hash(&start, &algo).is_ok() {
+                            done += 1;
+                        }
+                    }
+                    _ => {
+                        if walk_chain(&start, &algo, pwd_len, pwd_len, &allowed, num_links, None)
+                            .is_some()
+                        {
+                            done += num_links as u64;
+                        }
+                    }
+                }
+            }
+            done
+        }));
+    }
+
+    let mut done = 0;
+    for handle in handles {
+        done += handle.join().map_err(|_| "Benchmark thread panicked")?;
+    }
+    Ok(done as f64 / started.elapsed().as_secs_f64())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(algorithms: &[&str], lengths: Vec<usize>, num_links: usize) -> BenchConfig {
+        BenchConfig {
+            algorithms: algorithms.iter().map(|a| a.to_string()).collect(),
+            threads: vec![1, 2],
+            lengths,
+            duration: Duration::from_millis(5),
+            num_links,
+        }
+    }
+
+    #[test]
+    fn every_combination_is_measured() -> Result<(), Box<dyn std::error::Error>> {
+        let results = run_benchmark(&config(&["md5", "sha256"], vec![4, 6], 10))?;
+        // 2 algorithms, 2 lengths, 2 thread counts, hash and chain
+        assert_eq!(results.len(), 16);
+        assert!(results.iter().all(|result| result.rate > 0.0));
+        assert_eq!(
+            results
+                .iter()
+                .filter(|result| result.kind == "chain")
+                .count(),
+            8
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn bad_configs_are_refused() {
+        assert!(run_benchmark(&config(&["md5"], vec![4], 0)).is_err());
+        assert!(run_benchmark(&config(&["md5"], vec![0], 10)).is_err());
+        assert!(run_benchmark(&config(&["nope"], vec![4], 10)).is_err());
+    }
+}
@@ -0,0 +1,128 @@
+// Checkpoints for rainbow table generation
+//
+// While a table is generated, finished chains are appended to `<out>.partial` and
+// `<out>.checkpoint` records how many chains are done and how long the partial file
+// was at that point. A resumed run truncates the partial file back to that length,
+// skips the finished chains and carries on.
+
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+
+// A finished chain before its start is rebuilt: (index, end, links)
+pub(crate) type WalkedChain = (u64, String, usize);
+
+// Progress of an interrupted table generation
+#[derive(Debug, Clone, PartialEq)]
+pub struct Checkpoint {
+    // Settings of the run, a resume with different settings is refused
+    pub fingerprint: String,
+    // Chains 0..processed are finished and stored in the partial file
+    pub processed: u64,
+    pub partial_len: u64,
+}
+
+impl Checkpoint {
+    // Loads a checkpoint, None when there is none
+    pub fn load(path: &str) -> Result<Option<Checkpoint>, Box<dyn std::error::Error>> {
+        let text = match std::fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+
+        let mut fingerprint = None;
+        let mut processed = None;
+        let mut partial_len = None;
+        for line in text.lines() {
+            match line.split_once('=') {
+                Some(("fingerprint", value)) => fingerprint = Some(value.to_string()),
+                Some(("processed", value)) => processed = Some(value.parse()?),
+                Some(("partial_len", value)) => partial_len = Some(value.parse()?),
+                _ => {}
+            }
+        }
+
+        match (fingerprint, processed, partial_len) {
+            (Some(fingerprint), Some(processed), Some(partial_len)) => Ok(Some(Checkpoint {
+                fingerprint,
+                processed,
+                partial_len,
+            })),
+            _ => Err(format!("Checkpoint '{}' is incomplete", path).into()),
+        }
+    }
+
+    // Saves the checkpoint through a temporary file so it is never half written
+    pub fn save(&self, path: &str) -> Result<(), Box<dyn std::error::Error>> {
+        let tmp = format!("{}.tmp", path);
+        let mut f = File::create(&tmp)?;
+        writeln!(f, "fingerprint={}", self.fingerprint)?;
+        writeln!(f, "processed={}", self.processed)?;
+        writeln!(f, "partial_len={}", self.partial_len)?;
+        f.sync_all()?;
+        std::fs::rename(&tmp, path)?;
+        Ok(())
+    }
+}
+
+// Opens the partial chain file, either fresh or cut back to what a checkpoint covers
+pub(crate) fn open_partial(
+    path: &str,
+    checkpoint: Option<&Checkpoint>,
+) -> Result<File, Box<dyn std::error::Error>> {
+    match checkpoint {
+        Some(checkpoint) => {
+            let mut f = OpenOptions::new().read(true).write(true).open(path)?;
+            if f.metadata()?.len() < checkpoint.partial_len {
+                return Err(
+                    format!("Partial file '{}' is shorter than its checkpoint", path).into(),
+                );
+            }
+            f.set_len(checkpoint.partial_len)?;
+            f.seek(SeekFrom::End(0))?;
+            Ok(f)
+        }
+        None => Ok(File::create(path)?),
+    }
+}
+
+// Appends finished chains as (index, links, end) records and flushes them to disk
+pub(crate) fn append_chains(
+    f: &mut File,
+    chains: &[WalkedChain],
+) -> Result<u64, Box<dyn std::error::Error>> {
+    let mut buf = Vec::new();
+    for (index, end, links) in chains {
+        buf.extend_from_slice(&index.to_be_bytes());
+        buf.extend_from_slice(&(*links as u32).to_be_bytes());
+        buf.push(end.len() as u8);
+        buf.extend_from_slice(end.as_bytes());
+    }
+    f.write_all(&buf)?;
+    f.sync_data()?;
+    Ok(f.stream_position()?)
+}
+
+// Reads every (index, links, end) record back from the partial file
+pub(crate) fn read_partial(path: &str) -> Result<Vec<WalkedChain>, Box<dyn std::error::Error>> {
+    let mut data = Vec::new();
+    File::open(path)?.read_to_end(&mut data)?;
+
+    let mut chains = Vec::new();
+    let mut pos = 0;
+    while pos < data.len() {
+        let fixed = data
+            .get(pos..pos + 13)
+            .ok_or("Truncated record in partial file")?;
+        let index = u64::from_be_bytes(fixed[..8].try_into()?);
+        let links = u32::from_be_bytes(fixed[8..12].try_into()?) as usize;
+        let len = fixed[12] as usize;
+        pos += 13;
+        let end = data
+            .get(pos..pos + len)
+            .ok_or("Truncated record in partial file")?;
+        pos += len;
+        chains.push((index, String::from_utf8_lossy(end).to_string(), links));
+    }
+    Ok(chains)
+}
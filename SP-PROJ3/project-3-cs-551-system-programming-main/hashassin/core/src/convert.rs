@@ -0,0 +1,286 @@
+// Converts hash files and rainbow tables between format versions
+//
+// Upgrades are always lossless. Downgrades keep every chain and digest but can only
+// drop features the older format has no room for when that does not change what the
+// file means: indexed starts are written out in full and prefix compression is undone.
+// Distinguished point tables, salted hash files and custom KDF parameters cannot go back.
+
+use crate::{
+    FLAG_INDEXED_STARTS, FLAG_PREFIX_COMPRESSED, HashFile, RainbowTable, TABLE_MAGIC,
+    TABLE_VERSION, VERSION,
+};
+
+// What convert_file did
+#[derive(Debug, Clone)]
+pub struct Conversion {
+    // "hash file" or "rainbow table"
+    pub kind: &'static str,
+    pub from: u8,
+    pub to: u8,
+    // Digests or chains written
+    pub records: usize,
+}
+
+// Rewrites a hash file or rainbow table in another version, the newest when none is given
+pub fn convert_file(
+    in_file: &str,
+    out_file: &str,
+    to_version: Option<u8>,
+) -> Result<Conversion, Box<dyn std::error::Error>> {
+    let data = std::fs::read(in_file)?;
+
+    if data.starts_with(TABLE_MAGIC) {
+        let table = RainbowTable::from_bytes(&data)?;
+        let from = table.header.version;
+        let mut table = convert_table(table, to_version.unwrap_or(TABLE_VERSION))?;
+        table.write(out_file)?;
+        return Ok(Conversion {
+            kind: "rainbow table",
+            from,
+            to: table.header.version,
+            records: table.chains.len(),
+        });
+    }
+
+    let file = HashFile::from_bytes(&data)?;
+    let from = file.version;
+    let file = convert_hash_file(file, to_version.unwrap_or(VERSION))?;
+    file.write(out_file)?;
+    Ok(Conversion {
+        kind: "hash file",
+        from,
+        to: file.version,
+        records: file.records.len(),
+    })
+}
+
+// Moves a table to another format version, failing when the target cannot express it
+pub fn convert_table(
+    mut table: RainbowTable,
+    version: u8,
+) -> Result<RainbowTable, Box<dyn std::error::Error>> {
+    if version == 0 || version > TABLE_VERSION {
+        return Err(format!("Unsupported rainbow table version: {}", version).into());
+    }
+    let header = &mut table.header;
+
+    if version < 4 {
+        if header.distinguished() {
+            return Err("Distinguished point tables need format v4 or later".into());
+        }
+        // Prefix compression is only a way of storing v4 end points
+        header.flags &= !FLAG_PREFIX_COMPRESSED;
+        header.point_bytes = 0;
+    }
+    if version < 3 && header.indexed_starts() {
+        // Older formats always store the start, so the table seed is no longer needed
+        header.flags &= !FLAG_INDEXED_STARTS;
+        header.table_seed = 0;
+        header.index_bytes = 0;
+    }
+    if version == 1 && header.min_len != header.max_len {
+        return Err("v1 tables only hold a single password length".into());
+    }
+    if version < 5 {
+        header.body_len = 0;
+        header.body_crc = 0;
+    }
+
+    header.version = version;
+    Ok(table)
+}
+
+// Moves a hash file to another format version, failing when the target cannot express it
+pub fn convert_hash_file(
+    mut file: HashFile,
+    version: u8,
+) -> Result<HashFile, Box<dyn std::error::Error>> {
+    if version == 0 || version > VERSION {
+        return Err(format!("Unsupported hash file version: {}", version).into());
+    }
+    file.version = version;
+    // Serializing checks that the target version can hold salts and parameters
+    file.to_bytes()?;
+    Ok(file)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{FLAG_DISTINGUISHED, HASH_FLAG_SALTED, TableHeader, index_start};
+
+    fn sample_table(version: u8, min_len: usize, max_len: usize, flags: u8) -> RainbowTable {
+        let allowed: Vec<u8> = (32u8..=126u8).collect();
+        let chains = (0..50u64)
+            .map(|i| {
+                let start = if flags & FLAG_INDEXED_STARTS != 0 {
+                    index_start(99, i, min_len, max_len, &allowed)
+                } else {
+                    index_start(1, i, min_len, max_len, &allowed)
+                };
+                let end = index_start(2, i, min_len, max_len, &allowed);
+                (i, start, end)
+            })
+            .collect();
+        RainbowTable {
+            header: TableHeader {
+                version,
+                algorithm: "md5".to_string(),
+                min_len,
+                max_len,
+                charset_size: allowed.len() as u128,
+                num_links: 10,
+                ascii_offset: 32,
+                flags,
+                table_seed: if flags & FLAG_INDEXED_STARTS != 0 {
+                    99
+                } else {
+                    0
+                },
+                index_bytes: 0,
+                point_bytes: 0,
+                dp_bits: if flags & FLAG_DISTINGUISHED != 0 {
+                    3
+                } else {
+                    0
+                },
+                body_len: 0,
+                body_crc: 0,
+            },
+            chains,
+        }
+    }
+
+    // Serializes and parses a table back, which is what a conversion on disk does
+    fn reload(table: RainbowTable) -> Result<RainbowTable, Box<dyn std::error::Error>> {
+        let mut table = table;
+        RainbowTable::from_bytes(&table.to_bytes()?)
+    }
+
+    fn sorted_chains(table: &RainbowTable) -> Vec<(String, String)> {
+        let mut chains: Vec<_> = table
+            .chains
+            .iter()
+            .map(|(_, start, end)| (start.clone(), end.clone()))
+            .collect();
+        chains.sort();
+        chains
+    }
+
+    // Converts `original` to `to` and back, checking the chains survive both trips
+    fn assert_round_trip(original: RainbowTable, to: u8) -> Result<(), Box<dyn std::error::Error>> {
+        let from = original.header.version;
+        let original = reload(original)?;
+        let converted = reload(convert_table(original.clone(), to)?)?;
+        assert_eq!(converted.header.version, to);
+        assert_eq!(sorted_chains(&converted), sorted_chains(&original));
+
+        let back = reload(convert_table(converted, from)?)?;
+        assert_eq!(back.header.version, from);
+        assert_eq!(sorted_chains(&back), sorted_chains(&original));
+        assert_eq!(back.header.algorithm, original.header.algorithm);
+        assert_eq!(back.header.num_links, original.header.num_links);
+        assert_eq!(
+            (back.header.min_len, back.header.max_len),
+            (original.header.min_len, original.header.max_len)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn table_round_trips_between_every_version() -> Result<(), Box<dyn std::error::Error>> {
+        for from in 1..=TABLE_VERSION {
+            for to in 1..=TABLE_VERSION {
+                assert_round_trip(sample_table(from, 5, 5, 0), to)?;
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn variable_length_table_round_trips_from_v2() -> Result<(), Box<dyn std::error::Error>> {
+        for from in 2..=TABLE_VERSION {
+            for to in 2..=TABLE_VERSION {
+                assert_round_trip(sample_table(from, 3, 6, 0), to)?;
+            }
+        }
+        assert!(convert_table(sample_table(5, 3, 6, 0), 1).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn indexed_table_round_trips_from_v3() -> Result<(), Box<dyn std::error::Error>> {
+        for from in 3..=TABLE_VERSION {
+            for to in 3..=TABLE_VERSION {
+                let original = sample_table(from, 4, 6, FLAG_INDEXED_STARTS);
+                let converted = reload(convert_table(original, to)?)?;
+                assert!(converted.header.indexed_starts());
+                assert_eq!(converted.header.table_seed, 99);
+                assert_round_trip(sample_table(from, 4, 6, FLAG_INDEXED_STARTS), to)?;
+            }
+        }
+
+        // Going below v3 writes the starts out in full
+        let flat = reload(convert_table(
+            sample_table(5, 4, 6, FLAG_INDEXED_STARTS),
+            2,
+        )?)?;
+        assert!(!flat.header.indexed_starts());
+        assert_eq!(
+            sorted_chains(&flat),
+            sorted_chains(&reload(sample_table(5, 4, 6, FLAG_INDEXED_STARTS))?)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn compressed_and_distinguished_tables_round_trip() -> Result<(), Box<dyn std::error::Error>> {
+        for flags in [FLAG_PREFIX_COMPRESSED, FLAG_DISTINGUISHED] {
+            assert_round_trip(sample_table(4, 4, 6, flags), 5)?;
+            assert_round_trip(sample_table(5, 4, 6, flags), 4)?;
+        }
+        let dp = reload(convert_table(sample_table(5, 4, 6, FLAG_DISTINGUISHED), 4)?)?;
+        assert!(dp.header.distinguished());
+        assert_eq!(dp.header.dp_bits, 3);
+
+        // Compression can be undone, distinguished points cannot
+        let plain = convert_table(sample_table(5, 4, 6, FLAG_PREFIX_COMPRESSED), 3)?;
+        assert_eq!(plain.header.flags & FLAG_PREFIX_COMPRESSED, 0);
+        assert!(convert_table(sample_table(5, 4, 6, FLAG_DISTINGUISHED), 3).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn hash_file_round_trips_between_every_version() -> Result<(), Box<dyn std::error::Error>> {
+        for algorithm in ["md5", "sha256", "sha3_512", "scrypt"] {
+            let len = crate::digest_len(algorithm)?;
+            let digests = (0..20u8).map(|i| vec![i; len]).collect();
+            let newest = HashFile::new(algorithm, 8, digests)?;
+            for from in 1..=VERSION {
+                for to in 1..=VERSION {
+                    let original = convert_hash_file(newest.clone(), from)?;
+                    let original = HashFile::from_bytes(&original.to_bytes()?)?;
+                    let converted = convert_hash_file(original.clone(), to)?;
+                    let converted = HashFile::from_bytes(&converted.to_bytes()?)?;
+                    assert_eq!(converted.version, to);
+                    assert_eq!(converted.records, original.records);
+
+                    let back = convert_hash_file(converted, from)?;
+                    assert_eq!(HashFile::from_bytes(&back.to_bytes()?)?, original);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn salted_hash_file_cannot_go_to_v1() -> Result<(), Box<dyn std::error::Error>> {
+        let mut file = HashFile::new("md5", 8, vec![vec![1; 16]])?;
+        file.flags |= HASH_FLAG_SALTED;
+        file.records[0].0 = b"salt".to_vec();
+        let reloaded = HashFile::from_bytes(&file.to_bytes()?)?;
+        assert_eq!(reloaded.records, file.records);
+        assert!(convert_hash_file(file, 1).is_err());
+        Ok(())
+    }
+}
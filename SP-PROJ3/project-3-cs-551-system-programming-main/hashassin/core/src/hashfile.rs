@@ -0,0 +1,315 @@
+// The hash file format: a header naming the algorithm followed by the digests
+//
+// v1 is a bare version byte, the algorithm name, the password length and the digests.
+// v2 starts with a magic string and adds the digest length, the number of entries,
+// flags, optional KDF parameters and CRC32 checksums of the header and the records:
+//
+//   "hashfile" | version | algo_len | algorithm | pwd_len | digest_len | count u64 | flags
+//   | [kdf_len | kdf params] | body_crc u32 | header_crc u32 | records
+//
+// Salted records are [salt_len][salt][digest], unsalted records are just the digest.
+
+use crate::{VERSION, digest_len};
+
+// Magic bytes at the start of every v2 and later hash file
+pub const HASH_MAGIC: &[u8; 8] = b"hashfile";
+
+// Hash file flag: every record carries its own salt
+pub const HASH_FLAG_SALTED: u8 = 0x01;
+// Hash file flag: the header stores the KDF parameters the digests were made with
+pub const HASH_FLAG_KDF_PARAMS: u8 = 0x02;
+// Every flag this version of the code understands
+const KNOWN_HASH_FLAGS: u8 = HASH_FLAG_SALTED | HASH_FLAG_KDF_PARAMS;
+
+// One entry as (salt, digest), the salt is empty for unsalted files
+pub type HashRecord = (Vec<u8>, Vec<u8>);
+
+// A whole hash file held in memory
+#[derive(Debug, Clone, PartialEq)]
+pub struct HashFile {
+    pub version: u8,
+    pub algorithm: String,
+    // 0 when the passwords have varying lengths
+    pub pwd_len: u8,
+    pub digest_len: usize,
+    pub flags: u8,
+    // Only meaningful when HASH_FLAG_KDF_PARAMS is set
+    pub kdf_params: Vec<u8>,
+    pub records: Vec<HashRecord>,
+}
+
+// KDF parameters compute_hash uses for an algorithm, empty for plain digests
+// scrypt stores log2(N) as one byte followed by r and p as big-endian u32
+pub(crate) fn default_kdf_params(algorithm: &str) -> Vec<u8> {
+    match algorithm.to_lowercase().as_str() {
+        "scrypt" => {
+            let mut params = vec![14];
+            params.extend_from_slice(&8u32.to_be_bytes());
+            params.extend_from_slice(&1u32.to_be_bytes());
+            params
+        }
+        _ => Vec::new(),
+    }
+}
+
+impl HashFile {
+    // A file in the newest format for unsalted digests made by compute_hash
+    pub fn new(
+        algorithm: &str,
+        pwd_len: u8,
+        digests: Vec<Vec<u8>>,
+    ) -> Result<HashFile, Box<dyn std::error::Error>> {
+        let kdf_params = default_kdf_params(algorithm);
+        Ok(HashFile {
+            version: VERSION,
+            algorithm: algorithm.to_string(),
+            pwd_len,
+            digest_len: digest_len(algorithm)?,
+            flags: if kdf_params.is_empty() {
+                0
+            } else {
+                HASH_FLAG_KDF_PARAMS
+            },
+            kdf_params,
+            records: digests.into_iter().map(|d| (Vec::new(), d)).collect(),
+        })
+    }
+
+    pub fn is_salted(&self) -> bool {
+        self.flags & HASH_FLAG_SALTED != 0
+    }
+
+    // Parses a v1 or v2 hash file, checking the checksums of v2 files
+    pub fn from_bytes(data: &[u8]) -> Result<HashFile, Box<dyn std::error::Error>> {
+        if data.starts_with(HASH_MAGIC) {
+            return Self::parse_v2(data);
+        }
+        if data.starts_with(crate::TABLE_MAGIC) {
+            return Err("This is a rainbow table, not a hash file".into());
+        }
+        Self::parse_v1(data)
+    }
+
+    // Reads a hash file from disk
+    pub fn read(path: &str) -> Result<HashFile, Box<dyn std::error::Error>> {
+        HashFile::from_bytes(&std::fs::read(path)?)
+    }
+
+    fn parse_v1(data: &[u8]) -> Result<HashFile, Box<dyn std::error::Error>> {
+        let (&version, rest) = data.split_first().ok_or("Hash file is empty")?;
+        if version != 1 {
+            return Err(format!("Unsupported hash file version: {}", version).into());
+        }
+        let (&algo_len, rest) = rest.split_first().ok_or("Hash file header is truncated")?;
+        let name = rest
+            .get(..algo_len as usize)
+            .ok_or("Hash file header is truncated")?;
+        let algorithm = String::from_utf8(name.to_vec())?;
+        let pwd_len = *rest
+            .get(algo_len as usize)
+            .ok_or("Hash file header is truncated")?;
+        let body = &rest[algo_len as usize + 1..];
+
+        // v1 relies on the algorithm to know the digest length
+        let hash_len = digest_len(&algorithm)?;
+        if !body.len().is_multiple_of(hash_len) {
+            return Err(format!(
+                "Hash file body is {} bytes, not a multiple of the {} byte digest",
+                body.len(),
+                hash_len
+            )
+            .into());
+        }
+
+        // v1 files have the compute_hash parameters implicitly, keep them so upgrades are lossless
+        let kdf_params = default_kdf_params(&algorithm);
+        Ok(HashFile {
+            version,
+            pwd_len,
+            digest_len: hash_len,
+            flags: if kdf_params.is_empty() {
+                0
+            } else {
+                HASH_FLAG_KDF_PARAMS
+            },
+            kdf_params,
+            records: body
+                .chunks(hash_len)
+                .map(|d| (Vec::new(), d.to_vec()))
+                .collect(),
+            algorithm,
+        })
+    }
+
+    fn parse_v2(data: &[u8]) -> Result<HashFile, Box<dyn std::error::Error>> {
+        let truncated = "Hash file header is truncated";
+        let mut pos = HASH_MAGIC.len();
+        let version = *data.get(pos).ok_or(truncated)?;
+        pos += 1;
+        if version < 2 || version > VERSION {
+            return Err(format!("Unsupported hash file version: {}", version).into());
+        }
+        let algo_len = *data.get(pos).ok_or(truncated)? as usize;
+        pos += 1;
+        let algorithm =
+            String::from_utf8(data.get(pos..pos + algo_len).ok_or(truncated)?.to_vec())?;
+        pos += algo_len;
+        let pwd_len = *data.get(pos).ok_or(truncated)?;
+        let hash_len = *data.get(pos + 1).ok_or(truncated)? as usize;
+        pos += 2;
+        let count = u64::from_be_bytes(data.get(pos..pos + 8).ok_or(truncated)?.try_into()?);
+        pos += 8;
+        let flags = *data.get(pos).ok_or(truncated)?;
+        pos += 1;
+        if flags & !KNOWN_HASH_FLAGS != 0 {
+            return Err(format!("Unsupported hash file flags: {:#04x}", flags).into());
+        }
+        let mut kdf_params = Vec::new();
+        if flags & HASH_FLAG_KDF_PARAMS != 0 {
+            let kdf_len = *data.get(pos).ok_or(truncated)? as usize;
+            pos += 1;
+            kdf_params = data.get(pos..pos + kdf_len).ok_or(truncated)?.to_vec();
+            pos += kdf_len;
+        }
+        let body_crc = u32::from_be_bytes(data.get(pos..pos + 4).ok_or(truncated)?.try_into()?);
+        let header_crc =
+            u32::from_be_bytes(data.get(pos + 4..pos + 8).ok_or(truncated)?.try_into()?);
+        if crc32fast::hash(&data[..pos + 4]) != header_crc {
+            return Err("Hash file header checksum mismatch".into());
+        }
+        pos += 8;
+
+        // The stored length wins, but it must agree with algorithms we know
+        if let Ok(expected) = digest_len(&algorithm)
+            && expected != hash_len
+        {
+            return Err(format!(
+                "Hash file says {} digests are {} bytes, expected {}",
+                algorithm, hash_len, expected
+            )
+            .into());
+        }
+        if hash_len == 0 {
+            return Err("Hash file digest length is zero".into());
+        }
+
+        let body = &data[pos..];
+        if crc32fast::hash(body) != body_crc {
+            return Err("Hash file body checksum mismatch".into());
+        }
+        let salted = flags & HASH_FLAG_SALTED != 0;
+        let mut records = Vec::new();
+        let mut pos = 0;
+        while pos < body.len() {
+            let mut salt = Vec::new();
+            if salted {
+                let salt_len = body[pos] as usize;
+                salt = body
+                    .get(pos + 1..pos + 1 + salt_len)
+                    .ok_or("Truncated hash record")?
+                    .to_vec();
+                pos += 1 + salt_len;
+            }
+            let digest = body
+                .get(pos..pos + hash_len)
+                .ok_or("Truncated hash record")?;
+            pos += hash_len;
+            records.push((salt, digest.to_vec()));
+        }
+        if records.len() as u64 != count {
+            return Err(format!(
+                "Hash file holds {} entries, header says {}",
+                records.len(),
+                count
+            )
+            .into());
+        }
+
+        Ok(HashFile {
+            version,
+            algorithm,
+            pwd_len,
+            digest_len: hash_len,
+            flags,
+            kdf_params,
+            records,
+        })
+    }
+
+    // Serializes the file in the layout of `self.version`
+    pub fn to_bytes(&self) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+        if self.algorithm.len() > u8::MAX as usize {
+            return Err("Algorithm name is too long".into());
+        }
+        if let Some((salt, digest)) = self
+            .records
+            .iter()
+            .find(|(salt, digest)| digest.len() != self.digest_len || salt.len() > u8::MAX as usize)
+        {
+            return Err(format!(
+                "Hash record with a {} byte salt and {} byte digest does not fit the file",
+                salt.len(),
+                digest.len()
+            )
+            .into());
+        }
+
+        let mut out = Vec::new();
+        match self.version {
+            1 => {
+                // v1 has nowhere to put salts or parameters other than the implicit ones
+                if self.is_salted() {
+                    return Err("Salted hash files cannot be stored as v1".into());
+                }
+                if self.kdf_params != default_kdf_params(&self.algorithm) {
+                    return Err("Custom KDF parameters cannot be stored as v1".into());
+                }
+                out.push(1);
+                out.push(self.algorithm.len() as u8);
+                out.extend_from_slice(self.algorithm.as_bytes());
+                out.push(self.pwd_len);
+                for (_, digest) in &self.records {
+                    out.extend_from_slice(digest);
+                }
+            }
+            2 => {
+                let mut body = Vec::new();
+                for (salt, digest) in &self.records {
+                    if self.is_salted() {
+                        body.push(salt.len() as u8);
+                        body.extend_from_slice(salt);
+                    }
+                    body.extend_from_slice(digest);
+                }
+
+                out.extend_from_slice(HASH_MAGIC);
+                out.push(self.version);
+                out.push(self.algorithm.len() as u8);
+                out.extend_from_slice(self.algorithm.as_bytes());
+                out.push(self.pwd_len);
+                out.push(u8::try_from(self.digest_len)?);
+                out.extend_from_slice(&(self.records.len() as u64).to_be_bytes());
+                out.push(self.flags);
+                if self.flags & HASH_FLAG_KDF_PARAMS != 0 {
+                    out.push(u8::try_from(self.kdf_params.len())?);
+                    out.extend_from_slice(&self.kdf_params);
+                }
+                out.extend_from_slice(&crc32fast::hash(&body).to_be_bytes());
+                let header_crc = crc32fast::hash(&out);
+                out.extend_from_slice(&header_crc.to_be_bytes());
+                out.extend_from_slice(&body);
+            }
+            version => return Err(format!("Unsupported hash file version: {}", version).into()),
+        }
+        Ok(out)
+    }
+
+    // Writes the file, going through a temporary file so readers never see half of it
+    pub fn write(&self, path: &str) -> Result<(), Box<dyn std::error::Error>> {
+        let bytes = self.to_bytes()?;
+        let tmp = format!("{}.tmp", path);
+        std::fs::write(&tmp, &bytes)?;
+        std::fs::rename(&tmp, path)?;
+        Ok(())
+    }
+}
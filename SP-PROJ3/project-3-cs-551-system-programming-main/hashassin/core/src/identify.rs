@@ -0,0 +1,277 @@
+// Guesses the algorithm behind a digest or a dump of digests
+//
+// Modular crypt strings ($1$, $2b$, $6$, ...) name their scheme, so a known prefix is
+// certain. Bare digests only reveal their length, so every algorithm with that digest
+// length is a candidate, weighted by how common it is and nudged by the character set.
+// Hashassin files store the algorithm in their header.
+
+use crate::{ALGORITHMS, HASH_MAGIC, HashFile, TABLE_MAGIC, TableHeader};
+use serde::Serialize;
+
+// Algorithms by digest length in bytes, with a rough weight for how common each one is
+const BY_LENGTH: &[(usize, &str, f64)] = &[
+    (16, "md5", 0.6),
+    (16, "ntlm", 0.25),
+    (16, "md4", 0.15),
+    (20, "sha1", 0.8),
+    (20, "ripemd160", 0.2),
+    (28, "sha224", 0.6),
+    (28, "sha3_224", 0.4),
+    (32, "sha256", 0.55),
+    (32, "scrypt", 0.2),
+    (32, "sha3_256", 0.15),
+    (32, "blake2s", 0.1),
+    (48, "sha384", 0.7),
+    (48, "sha3_384", 0.3),
+    (64, "sha512", 0.45),
+    (64, "sha3_512", 0.35),
+    (64, "blake2b", 0.2),
+];
+
+// Modular crypt prefixes and the scheme they belong to
+const CRYPT_PREFIXES: &[(&str, &str)] = &[
+    ("$argon2id$", "argon2id"),
+    ("$argon2i$", "argon2i"),
+    ("$argon2d$", "argon2d"),
+    ("$2a$", "bcrypt"),
+    ("$2b$", "bcrypt"),
+    ("$2y$", "bcrypt"),
+    ("$1$", "md5crypt"),
+    ("$apr1$", "apr1"),
+    ("$5$", "sha256crypt"),
+    ("$6$", "sha512crypt"),
+    ("$7$", "scrypt"),
+    ("$scrypt$", "scrypt"),
+    ("$y$", "yescrypt"),
+    ("$pbkdf2-sha256$", "pbkdf2-sha256"),
+    ("$pbkdf2-sha512$", "pbkdf2-sha512"),
+];
+
+// Algorithms hashassin can hash get a boost, they are what our own dumps contain
+const SUPPORTED_BOOST: f64 = 1.5;
+// Dumping tools print NTLM in upper case hex
+const UPPERCASE_NTLM_BOOST: f64 = 4.0;
+
+// One possible algorithm, confidences of all candidates add up to 1
+#[derive(Debug, Clone, Serialize)]
+pub struct Candidate {
+    pub algorithm: String,
+    pub confidence: f64,
+    // True when hashassin can generate and crack this algorithm
+    pub supported: bool,
+    pub reason: String,
+}
+
+// What identify_file found in a file
+#[derive(Debug, Clone, Serialize)]
+pub struct Identification {
+    // "hash file", "rainbow table" or "text"
+    pub source: String,
+    // Number of digests, or chains for a table
+    pub entries: usize,
+    // Text lines that do not look like the first one
+    pub mismatched: usize,
+    pub candidates: Vec<Candidate>,
+}
+
+// Ranks the candidate algorithms for a single digest string
+pub fn identify(digest: &str) -> Result<Vec<Candidate>, Box<dyn std::error::Error>> {
+    let digest = digest.trim();
+    if digest.is_empty() {
+        return Err("Empty digest".into());
+    }
+
+    if digest.starts_with('$') {
+        return match CRYPT_PREFIXES
+            .iter()
+            .find(|(prefix, _)| digest.starts_with(prefix))
+        {
+            Some((prefix, scheme)) => Ok(vec![Candidate {
+                algorithm: scheme.to_string(),
+                confidence: 1.0,
+                // Salted crypt schemes are not what hashassin computes, even for scrypt
+                supported: false,
+                reason: format!("modular crypt prefix {}", prefix),
+            }]),
+            None => Err(format!("Unknown modular crypt prefix in '{}'", digest).into()),
+        };
+    }
+
+    let (bytes, encoding) = if digest.chars().all(|c| c.is_ascii_hexdigit()) {
+        if !digest.len().is_multiple_of(2) {
+            return Err(format!("Odd number of hex digits in '{}'", digest).into());
+        }
+        (digest.len() / 2, "hex")
+    } else if digest.len().is_multiple_of(4)
+        // Base64 pads with at most two '=', so all-padding input is not a digest
+        && digest.len() - digest.trim_end_matches('=').len() <= 2
+        && digest
+            .trim_end_matches('=')
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '+' || c == '/')
+    {
+        let padding = digest.len() - digest.trim_end_matches('=').len();
+        (digest.len() / 4 * 3 - padding, "base64")
+    } else {
+        return Err(format!("'{}' is neither hex, base64 nor modular crypt", digest).into());
+    };
+    let uppercase = encoding == "hex" && digest.chars().any(|c| c.is_ascii_uppercase());
+
+    let mut candidates: Vec<Candidate> = BY_LENGTH
+        .iter()
+        .filter(|(len, _, _)| *len == bytes)
+        .map(|(_, algorithm, weight)| {
+            let supported = ALGORITHMS.contains(algorithm);
+            let mut weight = *weight;
+            let mut reason = format!("{} byte {} digest", bytes, encoding);
+            if supported {
+                weight *= SUPPORTED_BOOST;
+            }
+            if uppercase && *algorithm == "ntlm" {
+                weight *= UPPERCASE_NTLM_BOOST;
+                reason.push_str(", upper case hex");
+            }
+            Candidate {
+                algorithm: algorithm.to_string(),
+                confidence: weight,
+                supported,
+                reason,
+            }
+        })
+        .collect();
+    if candidates.is_empty() {
+        return Err(format!("No known algorithm has a {} byte digest", bytes).into());
+    }
+
+    let total: f64 = candidates.iter().map(|c| c.confidence).sum();
+    for candidate in &mut candidates {
+        candidate.confidence /= total;
+    }
+    candidates.sort_by(|a, b| b.confidence.total_cmp(&a.confidence));
+    Ok(candidates)
+}
+
+// Identifies a hashassin hash file, rainbow table or text file of digests
+pub fn identify_file(in_file: &str) -> Result<Identification, Box<dyn std::error::Error>> {
+    let data = std::fs::read(in_file)?;
+
+    // Rainbow tables start with their magic and name the algorithm in the header
+    if data.starts_with(TABLE_MAGIC) {
+        let (header, pos) = TableHeader::parse(&data)?;
+        let entries = header.count_chains(&data, pos)?;
+        return Ok(Identification {
+            source: "rainbow table".to_string(),
+            entries,
+            mismatched: 0,
+            candidates: vec![known(&header.algorithm, "rainbow table header")],
+        });
+    }
+
+    // Hash files name their algorithm too, v2 files are recognised by their magic
+    let hash_file = HashFile::from_bytes(&data);
+    if data.starts_with(HASH_MAGIC) || hash_file.is_ok() {
+        let file = hash_file?;
+        return Ok(Identification {
+            source: "hash file".to_string(),
+            entries: file.records.len(),
+            mismatched: 0,
+            candidates: vec![known(&file.algorithm, "hash file header")],
+        });
+    }
+
+    let text = String::from_utf8(data).map_err(|_| "Not a hashassin file or a text file")?;
+    let lines: Vec<&str> = text
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .collect();
+    let first = lines.first().ok_or("No digests found")?;
+    let candidates = identify(first)?;
+
+    // Lines of another length or scheme probably come from a different algorithm
+    let shape = |line: &str| match line.strip_prefix('$') {
+        Some(rest) => rest.split('$').next().unwrap_or("").to_string(),
+        None => line.len().to_string(),
+    };
+    let mismatched = lines
+        .iter()
+        .filter(|line| shape(line) != shape(first))
+        .count();
+
+    Ok(Identification {
+        source: "text".to_string(),
+        entries: lines.len(),
+        mismatched,
+        candidates,
+    })
+}
+
+// A candidate read from a header rather than guessed
+fn known(algorithm: &str, reason: &str) -> Candidate {
+    Candidate {
+        algorithm: algorithm.to_string(),
+        confidence: 1.0,
+        supported: ALGORITHMS.contains(&algorithm.to_lowercase().as_str()),
+        reason: reason.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    type TestResult = Result<(), Box<dyn std::error::Error>>;
+
+    fn algorithms(candidates: &[Candidate]) -> Vec<&str> {
+        candidates.iter().map(|c| c.algorithm.as_str()).collect()
+    }
+
+    #[test]
+    fn lower_case_hex_md5_ranks_md5_first() -> TestResult {
+        let candidates = identify(&hex::encode(md5::compute("password").0))?;
+        assert_eq!(algorithms(&candidates), ["md5", "ntlm", "md4"]);
+        assert!(candidates[0].supported);
+        let total: f64 = candidates.iter().map(|c| c.confidence).sum();
+        assert!((total - 1.0).abs() < 1e-9);
+        Ok(())
+    }
+
+    #[test]
+    fn upper_case_hex_favours_ntlm() -> TestResult {
+        let candidates = identify("8846F7EAEE8FB117AD06BDD830B7586C")?;
+        assert_eq!(candidates[0].algorithm, "ntlm");
+        assert!(candidates[0].reason.contains("upper case hex"));
+        assert!(!candidates[1].reason.contains("upper case hex"));
+        Ok(())
+    }
+
+    #[test]
+    fn padded_base64_counts_only_the_digest_bytes() -> TestResult {
+        // 16 bytes take 22 base64 characters and two '='
+        let candidates = identify("X03MO1qnZdYdgyfeuILPmQ==")?;
+        assert_eq!(algorithms(&candidates), ["md5", "ntlm", "md4"]);
+        assert!(candidates[0].reason.starts_with("16 byte base64"));
+        Ok(())
+    }
+
+    #[test]
+    fn malformed_digests_are_rejected() {
+        for digest in [
+            "====",
+            "abc",
+            "5f4dcc3b5aa765d61d8327deb882cf9",
+            "$xx$salt$hash",
+            "",
+        ] {
+            assert!(identify(digest).is_err(), "{:?} was accepted", digest);
+        }
+    }
+
+    #[test]
+    fn crypt_prefixes_name_their_scheme() -> TestResult {
+        let candidates = identify("$6$salt$hash")?;
+        assert_eq!(algorithms(&candidates), ["sha512crypt"]);
+        assert_eq!(candidates[0].confidence, 1.0);
+        Ok(())
+    }
+}
@@ -0,0 +1,351 @@
+// Shows the header, summary statistics and records of any hashassin file
+//
+// The file type is sniffed from its first bytes: rainbow tables and v2 hash files start
+// with their magic, v1 hash files with a version byte and a known algorithm, checkpoints
+// with their `fingerprint=` line, and potfiles are text lines of a digest and its password.
+
+use crate::checkpoint::read_partial;
+use crate::{Checkpoint, HASH_FLAG_KDF_PARAMS, HASH_MAGIC, HashFile, TABLE_MAGIC, TableHeader};
+use serde::{Serialize, Serializer};
+use std::collections::HashSet;
+
+// Header fields and statistics keep their order, so they serialize as ordered maps
+type Fields = Vec<(String, String)>;
+
+// Everything inspect found out about a file, records are paged by offset and limit
+#[derive(Debug, Clone, Serialize)]
+pub struct Inspection {
+    // "hash file", "rainbow table", "potfile" or "checkpoint"
+    pub kind: &'static str,
+    #[serde(serialize_with = "ordered_map")]
+    pub header: Fields,
+    #[serde(serialize_with = "ordered_map")]
+    pub stats: Fields,
+    pub total_records: usize,
+    pub offset: usize,
+    pub records: Vec<Vec<String>>,
+}
+
+// Sniffs the type of `in_file` and inspects it
+pub fn inspect(
+    in_file: &str,
+    offset: usize,
+    limit: Option<usize>,
+) -> Result<Inspection, Box<dyn std::error::Error>> {
+    let data = std::fs::read(in_file)?;
+
+    let page = Page {
+        offset,
+        limit: limit.unwrap_or(usize::MAX),
+    };
+    let hash_file = HashFile::from_bytes(&data);
+    let (kind, header, stats, total_records, records) = if data.starts_with(TABLE_MAGIC) {
+        inspect_table(&data, page)?
+    } else if data.starts_with(HASH_MAGIC) || hash_file.is_ok() {
+        inspect_hash_file(hash_file?, data.len(), page)
+    } else if data.starts_with(b"fingerprint=") {
+        inspect_checkpoint(in_file, page)?
+    } else if let Some(parsed) = inspect_potfile(&data, page) {
+        parsed
+    } else {
+        return Err(format!("'{}' is not a recognised hashassin file", in_file).into());
+    };
+
+    Ok(Inspection {
+        kind,
+        header,
+        stats,
+        total_records,
+        offset,
+        records,
+    })
+}
+
+// Kind, header, stats, total number of records and the records on the page
+type Parsed = (&'static str, Fields, Fields, usize, Vec<Vec<String>>);
+
+// The records to show, only these are decoded
+#[derive(Debug, Clone, Copy)]
+struct Page {
+    offset: usize,
+    limit: usize,
+}
+
+impl Page {
+    fn contains(&self, number: usize) -> bool {
+        number >= self.offset && number - self.offset < self.limit
+    }
+
+    fn select<T>(&self, records: impl Iterator<Item = T>) -> impl Iterator<Item = T> {
+        records.skip(self.offset).take(self.limit)
+    }
+}
+
+fn field(name: &str, value: impl ToString) -> (String, String) {
+    (name.to_string(), value.to_string())
+}
+
+fn inspect_table(data: &[u8], page: Page) -> Result<Parsed, Box<dyn std::error::Error>> {
+    let (header, pos) = TableHeader::parse(data)?;
+    let checksums = match header.verify_body(data, pos) {
+        Ok(()) if header.version >= 5 => "ok".to_string(),
+        Ok(()) => "none".to_string(),
+        Err(e) => format!("failed ({})", e),
+    };
+    // Only the chains on the page are decoded, v4 tables are sorted by end point so
+    // duplicates are neighbours, older ones have to remember every end point
+    let mut chains = 0;
+    let mut records = Vec::new();
+    let mut unique = 0;
+    let mut prev_end: Option<Vec<u8>> = None;
+    let mut ends: HashSet<Vec<u8>> = HashSet::new();
+    header.scan_records(data, pos, |record| {
+        if page.contains(chains) {
+            let (_, start, end) = record.decode()?;
+            records.push(vec![start, end]);
+        }
+        chains += 1;
+        let end = record.end_bytes();
+        if header.version >= 4 {
+            if prev_end.as_deref() != Some(end) {
+                unique += 1;
+                prev_end = Some(end.to_vec());
+            }
+        } else if ends.insert(end.to_vec()) {
+            unique += 1;
+        }
+        Ok(true)
+    })?;
+
+    let mut fields = vec![
+        field("version", header.version),
+        field("algorithm", &header.algorithm),
+        field(
+            "password length",
+            format!("{}-{}", header.min_len, header.max_len),
+        ),
+        field("key size", header.charset_size),
+        field("num links", header.num_links),
+        field("ascii offset", header.ascii_offset),
+        field("flags", format!("{:#04x}", header.flags)),
+    ];
+    if header.indexed_starts() {
+        fields.push(field("table seed", header.table_seed));
+    }
+    if header.distinguished() {
+        fields.push(field("distinguished point bits", header.dp_bits));
+    }
+
+    let stats = vec![
+        field("chains", chains),
+        field("unique endpoints", unique),
+        field("file size", data.len()),
+        field("checksums", checksums),
+    ];
+    Ok(("rainbow table", fields, stats, chains, records))
+}
+
+fn inspect_hash_file(file: HashFile, file_size: usize, page: Page) -> Parsed {
+    let mut fields = vec![
+        field("version", file.version),
+        field("algorithm", &file.algorithm),
+        field(
+            "password length",
+            if file.pwd_len == 0 {
+                "variable".to_string()
+            } else {
+                file.pwd_len.to_string()
+            },
+        ),
+        field("digest length", file.digest_len),
+        field("flags", format!("{:#04x}", file.flags)),
+    ];
+    if file.flags & HASH_FLAG_KDF_PARAMS != 0 {
+        fields.push(field("kdf params", hex::encode(&file.kdf_params)));
+    }
+
+    let unique = file
+        .records
+        .iter()
+        .map(|(_, digest)| digest)
+        .collect::<HashSet<_>>()
+        .len();
+    let stats = vec![
+        field("hashes", file.records.len()),
+        field("unique hashes", unique),
+        field("file size", file_size),
+        // A v2 file that parsed has already passed its checksums
+        field("checksums", if file.version >= 2 { "ok" } else { "none" }),
+    ];
+    let salted = file.is_salted();
+    let records = page
+        .select(file.records.iter())
+        .map(|(salt, digest)| {
+            if salted {
+                vec![hex::encode(salt), hex::encode(digest)]
+            } else {
+                vec![hex::encode(digest)]
+            }
+        })
+        .collect();
+    ("hash file", fields, stats, file.records.len(), records)
+}
+
+fn inspect_checkpoint(in_file: &str, page: Page) -> Result<Parsed, Box<dyn std::error::Error>> {
+    let checkpoint = Checkpoint::load(in_file)?.ok_or("Checkpoint disappeared")?;
+    let fields = vec![
+        field("fingerprint", &checkpoint.fingerprint),
+        field("processed", checkpoint.processed),
+        field("partial length", checkpoint.partial_len),
+    ];
+
+    // The partial chains live next to the checkpoint, cut to the checkpointed length
+    let mut stats = Vec::new();
+    let mut total = 0;
+    let mut records = Vec::new();
+    if let Some(base) = in_file.strip_suffix(".checkpoint") {
+        let partial = format!("{}.partial", base);
+        match read_partial(&partial) {
+            Ok(chains) => {
+                stats.push(field("partial file", &partial));
+                stats.push(field("partial chains", chains.len()));
+                total = chains.len();
+                records = page
+                    .select(chains.into_iter())
+                    .map(|(index, end, links)| vec![index.to_string(), end, links.to_string()])
+                    .collect();
+            }
+            Err(e) => stats.push(field("partial file", format!("unreadable ({})", e))),
+        }
+    }
+    Ok(("checkpoint", fields, stats, total, records))
+}
+
+// Potfiles are `digest<TAB>password` lines as written by crack, `digest:password` also works
+fn inspect_potfile(data: &[u8], page: Page) -> Option<Parsed> {
+    let text = std::str::from_utf8(data).ok()?;
+    let mut entries = 0;
+    let mut not_found = 0;
+    let mut hashes = HashSet::new();
+    let mut digest_lengths = std::collections::BTreeSet::new();
+    let mut records = Vec::new();
+    for line in text.lines().filter(|line| !line.is_empty()) {
+        let (hash, password) = line.split_once('\t').or_else(|| line.split_once(':'))?;
+        if hash.is_empty() || !hash.chars().all(|c| c.is_ascii_hexdigit()) {
+            return None;
+        }
+        if page.contains(entries) {
+            records.push(vec![hash.to_string(), password.to_string()]);
+        }
+        entries += 1;
+        not_found += (password == "NOT FOUND") as usize;
+        hashes.insert(hash);
+        digest_lengths.insert(hash.len() / 2);
+    }
+    if entries == 0 {
+        return None;
+    }
+
+    let digest_lengths: Vec<String> = digest_lengths
+        .into_iter()
+        .map(|len| len.to_string())
+        .collect();
+    let stats = vec![
+        field("entries", entries),
+        field("cracked", entries - not_found),
+        field("not found", not_found),
+        field("unique hashes", hashes.len()),
+        field("digest length", digest_lengths.join(", ")),
+    ];
+    Some(("potfile", Vec::new(), stats, entries, records))
+}
+
+fn ordered_map<S: Serializer>(fields: &Fields, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_map(fields.iter().map(|(k, v)| (k, v)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::checkpoint::{append_chains, open_partial};
+    use crate::{ChainStarts, Hooks, TableParams, gen_rainbow_table};
+
+    type TestResult = Result<(), Box<dyn std::error::Error>>;
+
+    // Writes a 12 record file of each kind into a scratch directory
+    fn files(name: &str) -> Result<(std::path::PathBuf, Vec<String>), Box<dyn std::error::Error>> {
+        let dir =
+            std::env::temp_dir().join(format!("hashassin-inspect-{}-{}", std::process::id(), name));
+        std::fs::create_dir_all(&dir)?;
+        let path = |file: &str| dir.join(file).to_string_lossy().to_string();
+
+        let table = path("t.rainbow");
+        let params = TableParams {
+            algorithm: "md5".to_string(),
+            num_links: 5,
+            threads: 1,
+            len_range: Some((4, 4)),
+            compress: false,
+            dp_bits: None,
+            resume: false,
+            checkpoint_every: 1000,
+        };
+        let starts = ChainStarts::Indexed {
+            count: 12,
+            table_seed: 1,
+        };
+        gen_rainbow_table(starts, &table, &params, &Hooks::default())?;
+
+        let hashes = path("h.bin");
+        let digests = (0..12)
+            .map(|i| md5::compute(i.to_string()).0.to_vec())
+            .collect();
+        HashFile::new("md5", 0, digests)?.write(&hashes)?;
+
+        let checkpoint = path("c.rainbow.checkpoint");
+        let mut partial = open_partial(&path("c.rainbow.partial"), None)?;
+        let chains: Vec<_> = (0..12).map(|i| (i, format!("e{:02}", i), 5)).collect();
+        let partial_len = append_chains(&mut partial, &chains)?;
+        Checkpoint {
+            fingerprint: "test".to_string(),
+            processed: 12,
+            partial_len,
+        }
+        .save(&checkpoint)?;
+
+        Ok((dir, vec![table, hashes, checkpoint]))
+    }
+
+    #[test]
+    fn pages_respect_offset_and_limit() -> TestResult {
+        let (dir, files) = files("limit")?;
+        for file in &files {
+            let all = inspect(file, 0, None)?;
+            assert_eq!((all.total_records, all.records.len()), (12, 12), "{}", file);
+
+            let page = inspect(file, 4, Some(5))?;
+            assert_eq!(page.total_records, 12);
+            assert_eq!(page.offset, 4);
+            assert_eq!(page.records, all.records[4..9]);
+
+            // A page running over the end is cut short
+            assert_eq!(inspect(file, 10, Some(5))?.records, all.records[10..]);
+        }
+        std::fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn offset_past_the_end_gives_an_empty_page() -> TestResult {
+        let (dir, files) = files("past")?;
+        for file in &files {
+            for limit in [None, Some(3)] {
+                let page = inspect(file, 50, limit)?;
+                assert_eq!(page.total_records, 12, "{}", file);
+                assert!(page.records.is_empty());
+            }
+        }
+        std::fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,171 @@
+// Maps passwords to their integer index in the keyspace of a charset and length range
+//
+// Passwords are ordered by length first, then as base-`charset_size` numbers,
+// so every password with a length in min_len..=max_len gets a unique dense index.
+
+use num_bigint::BigUint;
+
+// Number of passwords with a length in min_len..=max_len
+pub fn keyspace_size(min_len: usize, max_len: usize, charset_size: u128) -> BigUint {
+    let base = BigUint::from(charset_size);
+    (min_len..=max_len).map(|len| base.pow(len as u32)).sum()
+}
+
+// Checks the charset is non-empty and made of single bytes from `ascii_offset` on
+pub fn check_charset(
+    ascii_offset: u8,
+    charset_size: u128,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if charset_size == 0 || ascii_offset as u128 + charset_size > 256 {
+        return Err(format!(
+            "Invalid charset of {} characters from offset {}",
+            charset_size, ascii_offset
+        )
+        .into());
+    }
+    Ok(())
+}
+
+// Fewest bytes that can hold any index of the keyspace
+pub fn point_bytes(
+    min_len: usize,
+    max_len: usize,
+    charset_size: u128,
+) -> Result<usize, Box<dyn std::error::Error>> {
+    let size = keyspace_size(min_len, max_len, charset_size);
+    if size == BigUint::default() {
+        return Err("Keyspace is empty".into());
+    }
+    let bits = (size - 1u32).bits();
+    Ok((bits.div_ceil(8) as usize).max(1))
+}
+
+// Encodes a password as a big-endian integer of exactly `width` bytes
+pub fn encode_password(
+    pwd: &str,
+    min_len: usize,
+    max_len: usize,
+    ascii_offset: u8,
+    charset_size: u128,
+    width: usize,
+) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    check_charset(ascii_offset, charset_size)?;
+    let len = pwd.len();
+    if len < min_len || len > max_len {
+        return Err(format!("Password length {} outside {}..={}", len, min_len, max_len).into());
+    }
+
+    // Skip past every shorter password in the range
+    let mut value = keyspace_size(min_len, len - 1, charset_size);
+
+    let mut digits = BigUint::default();
+    for byte in pwd.bytes() {
+        let digit = byte.wrapping_sub(ascii_offset) as u128;
+        if byte < ascii_offset || digit >= charset_size {
+            return Err(format!("Character {:?} is outside the charset", byte as char).into());
+        }
+        digits = digits * charset_size + digit;
+    }
+    value += digits;
+
+    let bytes = value.to_bytes_be();
+    if bytes.len() > width {
+        return Err("Encoded password does not fit the point width".into());
+    }
+    let mut out = vec![0u8; width - bytes.len()];
+    out.extend_from_slice(&bytes);
+    Ok(out)
+}
+
+// Decodes a big-endian keyspace index back into its password
+pub fn decode_password(
+    bytes: &[u8],
+    min_len: usize,
+    max_len: usize,
+    ascii_offset: u8,
+    charset_size: u128,
+) -> Result<String, Box<dyn std::error::Error>> {
+    check_charset(ascii_offset, charset_size)?;
+    let base = BigUint::from(charset_size);
+    let mut value = BigUint::from_bytes_be(bytes);
+
+    // Find the length bucket the index falls into
+    for len in min_len..=max_len {
+        let count = base.pow(len as u32);
+        if value < count {
+            let mut chars = vec![ascii_offset; len];
+            for slot in chars.iter_mut().rev() {
+                let digit = (&value % &base)
+                    .to_u32_digits()
+                    .first()
+                    .copied()
+                    .unwrap_or(0);
+                *slot = ascii_offset.wrapping_add(digit as u8);
+                value /= &base;
+            }
+            return Ok(String::from_utf8_lossy(&chars).to_string());
+        }
+        value -= count;
+    }
+
+    Err("Encoded password is outside the keyspace".into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn passwords_round_trip_across_length_boundaries() -> Result<(), Box<dyn std::error::Error>> {
+        // Three printable characters and lengths 1..=3 give 3 + 9 + 27 = 39 indices
+        let (min_len, max_len, offset, size) = (1, 3, b'a', 3);
+        let width = point_bytes(min_len, max_len, size)?;
+        assert_eq!(keyspace_size(min_len, max_len, size), BigUint::from(39u32));
+        assert_eq!(width, 1);
+
+        let passwords = ["a", "c", "aa", "cc", "aaa", "ccc"];
+        let indices = [0u8, 2, 3, 11, 12, 38];
+        for (pwd, index) in passwords.iter().zip(indices) {
+            let packed = encode_password(pwd, min_len, max_len, offset, size, width)?;
+            assert_eq!(packed, vec![index]);
+            assert_eq!(
+                decode_password(&packed, min_len, max_len, offset, size)?,
+                *pwd
+            );
+        }
+
+        // Every index decodes to a password that encodes back to it
+        for index in 0..39u8 {
+            let pwd = decode_password(&[index], min_len, max_len, offset, size)?;
+            assert_eq!(
+                encode_password(&pwd, min_len, max_len, offset, size, width)?,
+                vec![index]
+            );
+        }
+        assert!(decode_password(&[39], min_len, max_len, offset, size).is_err());
+        assert!(encode_password("aaaa", min_len, max_len, offset, size, width).is_err());
+        assert!(encode_password("d", min_len, max_len, offset, size, width).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn wide_points_round_trip() -> Result<(), Box<dyn std::error::Error>> {
+        let width = point_bytes(4, 8, 95)?;
+        for pwd in ["    ", "~~~~", "     ", "~~~~~~~", "        ", "~~~~~~~~"] {
+            let packed = encode_password(pwd, 4, 8, 32, 95, width)?;
+            assert_eq!(packed.len(), width);
+            assert_eq!(decode_password(&packed, 4, 8, 32, 95)?, pwd);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn empty_or_oversized_charset_is_an_error() {
+        assert!(point_bytes(1, 4, 0).is_err());
+        assert!(encode_password("a", 1, 4, 0, 0, 1).is_err());
+        assert!(decode_password(&[0], 1, 4, 0, 0).is_err());
+        assert!(check_charset(0, 256).is_ok());
+        assert!(check_charset(1, 256).is_err());
+        assert!(decode_password(&[0], 1, 4, 200, 100).is_err());
+    }
+}
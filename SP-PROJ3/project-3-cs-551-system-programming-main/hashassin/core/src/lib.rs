@@ -1,6 +1,7 @@
 #![deny(clippy::unwrap_used, clippy::expect_used)]
 
-use rand::Rng;
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
 use scrypt::{Params, scrypt};
 use sha2::{Digest, Sha256};
 use sha3::Sha3_512;
@@ -11,8 +12,59 @@ use std::thread;
 use stretto::Cache;
 use tracing::info;
 
-// Current version of our file format
-pub const VERSION: u8 = 1;
+mod analyze;
+mod auth;
+mod benchmark;
+mod checkpoint;
+mod convert;
+mod hashfile;
+mod identify;
+mod inspect;
+mod keyspace;
+mod merge;
+mod progress;
+mod protocol;
+mod table;
+mod verify;
+pub use analyze::{
+    TableAnalysis, TablePlan, analyze_table, estimate_success, measure_hash_rate, plan_table,
+};
+pub use auth::{
+    AuthToken, CHALLENGE_LEN, MAC_LEN, Scope, load_tokens, new_challenge, sign_challenge,
+    verify_response,
+};
+pub use benchmark::{BenchConfig, BenchResult, run_benchmark};
+pub use checkpoint::Checkpoint;
+pub use convert::{Conversion, convert_file, convert_hash_file, convert_table};
+pub use hashfile::{HASH_FLAG_KDF_PARAMS, HASH_FLAG_SALTED, HASH_MAGIC, HashFile, HashRecord};
+pub use identify::{Candidate, Identification, identify, identify_file};
+pub use inspect::{Inspection, inspect};
+pub use keyspace::{decode_password, encode_password, keyspace_size, point_bytes};
+pub use merge::{SplitBy, merge_tables, split_table};
+pub use progress::{CancelToken, CrackSink, Hooks, Progress};
+pub use protocol::{
+    MAX_CHUNK_SIZE, PROTOCOL_VERSION, RESPONSE_HEADER_LEN, RESPONSE_MAGIC, Status, TableInfo,
+    UPLOAD_FLAG_REPLACE, chunk_crc, decode_response_header, encode_response,
+    encode_response_header,
+};
+pub use table::{
+    ChainRecord, FLAG_DISTINGUISHED, FLAG_INDEXED_STARTS, FLAG_PREFIX_COMPRESSED, RainbowTable,
+    RawRecord, TABLE_MAGIC, TABLE_VERSION, TableHeader,
+};
+pub use verify::{VerifyReport, verify_table};
+
+use checkpoint::{WalkedChain, append_chains, open_partial, read_partial};
+use progress::Tracker;
+
+// Cheap items such as single hashes are reported to the progress hook in batches of this size
+const PROGRESS_BATCH: u64 = 256;
+
+// Current version of the hash file format
+// v1 is a bare version byte and the algorithm, v2 adds a magic, counts, flags and checksums
+pub const VERSION: u8 = 2;
+
+// Every algorithm compute_hash supports
+pub const ALGORITHMS: &[&str] = &["md5", "sha256", "sha3_512", "scrypt"];
 
 // This is a helper function that actually computes a single hash
 fn compute_hash(password: &str, algorithm: &str) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
@@ -35,7 +87,41 @@ fn compute_hash(password: &str, algorithm: &str) -> Result<Vec<u8>, Box<dyn std:
     }
 }
 
-fn reduce_hash(hash: &[u8], length: usize, allowed: &[u8]) -> String {
+// Length in bytes of the digest an algorithm produces
+pub fn digest_len(algorithm: &str) -> Result<usize, Box<dyn std::error::Error>> {
+    match algorithm.to_lowercase().as_str() {
+        "md5" => Ok(16),
+        "sha256" => Ok(32),
+        "sha3_512" => Ok(64),
+        "scrypt" => Ok(32),
+        _ => Err(format!("Unsupported algorithm: {}", algorithm).into()),
+    }
+}
+
+// Derives the start password of chain `index` from the table seed
+pub fn index_start(
+    table_seed: u64,
+    index: u64,
+    min_len: usize,
+    max_len: usize,
+    allowed: &[u8],
+) -> String {
+    let mut rng = ChaCha8Rng::seed_from_u64(mix_seed(table_seed, index));
+    let length = rng.gen_range(min_len..=max_len);
+    (0..length)
+        .map(|_| allowed[rng.gen_range(0..allowed.len())] as char)
+        .collect()
+}
+
+// Reduces a hash to a password whose length lies in min_len..=max_len
+// The length is picked from the last byte of the hash, so fixed-length tables reduce as before
+fn reduce_hash(hash: &[u8], min_len: usize, max_len: usize, allowed: &[u8]) -> String {
+    let length = if max_len > min_len {
+        let last = hash.last().copied().unwrap_or(0) as usize;
+        min_len + last % (max_len - min_len + 1)
+    } else {
+        min_len
+    };
     let mut pwd = String::with_capacity(length);
     let charset_len = allowed.len();
     for i in 0..length {
@@ -45,11 +131,32 @@ fn reduce_hash(hash: &[u8], length: usize, allowed: &[u8]) -> String {
     pwd
 }
 
+// True when the hash starts with `bits` zero bits, which marks a distinguished point
+fn is_distinguished(hash: &[u8], bits: u8) -> bool {
+    let full = (bits / 8) as usize;
+    let rest = bits % 8;
+    if hash.len() < full + (rest > 0) as usize {
+        return false;
+    }
+    hash[..full].iter().all(|b| *b == 0) && (rest == 0 || hash[full] >> (8 - rest) == 0)
+}
+
+// Mixes a seed with an index (thread or chain) so every index gets its own stream
+fn mix_seed(seed: u64, idx: u64) -> u64 {
+    // splitmix64 finalizer, keeps nearby indices far apart
+    let mut z = seed ^ idx.wrapping_mul(0x9E37_79B9_7F4A_7C15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
+
 // This function generates random passwords with given length and count
+// When a seed is given the output is the same for the same seed, count and threads
 pub fn gen_passwords(
-    chars: usize,   // How long each passwords would be
-    num: usize,     // How many passwords to make
-    threads: usize, // How many threads to use
+    chars: usize,      // How long each passwords would be
+    num: usize,        // How many passwords to make
+    threads: usize,    // How many threads to use
+    seed: Option<u64>, // Optional seed for reproducible output
 ) -> Result<Vec<String>, Box<dyn std::error::Error>> {
     // Basic input check
     if chars == 0 || num == 0 || threads == 0 {
@@ -59,9 +166,6 @@ pub fn gen_passwords(
     // ASCII characters we can use from space ' ' to tilde '~' everything
     let allowed: Vec<u8> = (32u8..=126u8).collect();
 
-    // Shared list where threads will store generated passwords
-    let passwords = Arc::new(Mutex::new(Vec::with_capacity(num)));
-
     // Figure out how many passwords each thread should make
     let per_thread = num / threads;
     let remainder = num % threads;
@@ -69,13 +173,16 @@ pub fn gen_passwords(
     let mut handles = Vec::new();
     for i in 0..threads {
         let allowed = allowed.clone();
-        let passwords = Arc::clone(&passwords);
 
         // Some threads might do 1 extra password if the count isn't even
         let count = per_thread + if i < remainder { 1 } else { 0 };
 
         let handle = thread::spawn(move || {
-            let mut rng = rand::thread_rng(); // Each thread gets its own random generator
+            // Each thread gets its own random generator, seeded if asked to
+            let mut rng = match seed {
+                Some(seed) => ChaCha8Rng::seed_from_u64(mix_seed(seed, i as u64)),
+                None => ChaCha8Rng::from_entropy(),
+            };
             let mut local = Vec::with_capacity(count);
 
             // Build a password by picking random characters
@@ -85,26 +192,22 @@ pub fn gen_passwords(
                     .collect();
                 local.push(pwd);
             }
-
-            // Safely add our passwords to the shared list
-            if let Ok(mut pwds) = passwords.lock() {
-                pwds.extend(local);
-            }
+            local
         });
 
         handles.push(handle);
     }
 
-    // Wait for all threads to finish
+    // Wait for all threads to finish, collecting in thread order so output is stable
+    let mut passwords = Vec::with_capacity(num);
     for handle in handles {
-        let _ = handle.join();
+        let local = handle
+            .join()
+            .map_err(|_| "Password generation thread panicked".to_string())?;
+        passwords.extend(local);
     }
 
-    // Gets our final password list back from the shared state
-    Arc::try_unwrap(passwords)
-        .map_err(|_| "Arc had multiple strong references".to_string())?
-        .into_inner()
-        .map_err(|e| Box::new(e) as Box<dyn std::error::Error>)
+    Ok(passwords)
 }
 
 // This function takes passwords from a file, hashes them, and saves to another file
@@ -113,6 +216,7 @@ pub fn gen_hashes(
     out_file: &str,  // File where we want to save hashes
     algorithm: &str, // Like "sha256" and etc..
     threads: usize,  // How many threads to use
+    hooks: &Hooks,   // Progress callback and cancel token
 ) -> Result<(), Box<dyn std::error::Error>> {
     // Read all passwords from the input file
     let file = File::open(in_file)?;
@@ -123,14 +227,20 @@ pub fn gen_hashes(
         return Err("No passwords found".into());
     }
 
-    // Check all passwords are same length
+    // A length of 0 in the header means the passwords have varying lengths
     let pwd_len = passwords[0].len();
-    if !passwords.iter().all(|pwd| pwd.len() == pwd_len) {
-        return Err("Passwords must all have the same length".into());
+    let pwd_len = if passwords.iter().all(|pwd| pwd.len() == pwd_len) {
+        pwd_len
+    } else {
+        0
+    };
+    if pwd_len > u8::MAX as usize {
+        return Err("Passwords longer than 255 characters are not supported".into());
     }
 
     // Shared list for threads to store hashes
     let hashes = Arc::new(Mutex::new(Vec::new()));
+    let tracker = Tracker::new(hooks, passwords.len() as u64, 0);
 
     // Spliting the work between threads
     let chunk_size = passwords.len().div_ceil(threads);
@@ -140,15 +250,27 @@ pub fn gen_hashes(
         let chunk = chunk.to_vec();
         let algo = algorithm.to_string();
         let hashes = Arc::clone(&hashes);
+        let tracker = Arc::clone(&tracker);
 
         let handle = thread::spawn(move || {
             let mut local = Vec::new();
+            let mut pending = 0;
             for pwd in chunk {
+                // Stop early when cancelled, the hashes so far are still written
+                if tracker.cancelled() {
+                    break;
+                }
                 // Safely add hashes to our shared list
                 if let Ok(hash) = compute_hash(&pwd, &algo) {
                     local.push(hash);
                 }
+                pending += 1;
+                if pending == PROGRESS_BATCH {
+                    tracker.advance(pending);
+                    pending = 0;
+                }
             }
+            tracker.advance(pending);
             if let Ok(mut h) = hashes.lock() {
                 h.extend(local);
             }
@@ -168,149 +290,400 @@ pub fn gen_hashes(
         .into_inner()
         .map_err(|e| Box::new(e) as Box<dyn std::error::Error>)?;
 
-    // Prepare output file format
-    let mut output = Vec::new();
-    output.push(VERSION);
-    output.push(algorithm.len() as u8);
-    output.extend_from_slice(algorithm.as_bytes());
-    output.push(pwd_len as u8);
+    // Write the header and all hashes to the output file
+    HashFile::new(algorithm, pwd_len as u8, hashes)?.write(out_file)?;
 
-    // Add all hashes to the output
-    for hash in hashes {
-        output.extend_from_slice(&hash);
+    if tracker.cancelled() {
+        return Err(format!(
+            "Cancelled after {} of {} passwords, partial hashes written to '{}'",
+            tracker.done(),
+            passwords.len(),
+            out_file
+        )
+        .into());
     }
 
-    // Write everything to the output file
-    let mut f = File::create(out_file)?;
-    f.write_all(&output)?;
-
     Ok(())
 }
 
 // This function shows hash file contents in human-readable format
 pub fn dump_hashes(in_file: &str) -> Result<(), Box<dyn std::error::Error>> {
-    // Read entire file into memory
-    let data = std::fs::read(in_file)?;
-
-    let version = data[0]; // First byte is version
-    let algo_len = data[1] as usize; // Second byte is algo name length
-    let algo = String::from_utf8(data[2..2 + algo_len].to_vec())?;
-    let pwd_len = data[2 + algo_len];
+    // Read and check the whole file, v1 and v2 are both accepted
+    let file = HashFile::read(in_file)
+        .map_err(|e| format!("'{}' is not a hash file ({}), try inspect", in_file, e))?;
 
     // Print file information
-    println!("VERSION: {version}");
-    println!("ALGORITHM: {algo}");
-    println!("PASSWORD LENGTH: {pwd_len}");
-
-    let mut pos = 2 + algo_len + 1;
-
-    // Figuring out how long each hash should be
-    let hash_len = match algo.as_str() {
-        "md5" => 16,
-        "sha256" => 32,
-        "sha3_512" => 64,
-        "scrypt" => 32,
-        _ => return Err("Unsupported algorithm".into()),
-    };
+    println!("VERSION: {}", file.version);
+    println!("ALGORITHM: {}", file.algorithm);
+    if file.pwd_len == 0 {
+        println!("PASSWORD LENGTH: variable");
+    } else {
+        println!("PASSWORD LENGTH: {}", file.pwd_len);
+    }
+    if file.version >= 2 {
+        println!("DIGEST LENGTH: {}", file.digest_len);
+        println!("ENTRIES: {}", file.records.len());
+        if file.flags & HASH_FLAG_KDF_PARAMS != 0 {
+            println!("KDF PARAMS: {}", hex::encode(&file.kdf_params));
+        }
+    }
 
-    while pos + hash_len <= data.len() {
-        println!("{}", hex::encode(&data[pos..pos + hash_len]));
-        pos += hash_len;
+    // Salted records are shown as salt:digest
+    for (salt, digest) in &file.records {
+        if file.is_salted() {
+            println!("{}:{}", hex::encode(salt), hex::encode(digest));
+        } else {
+            println!("{}", hex::encode(digest));
+        }
     }
 
     Ok(())
 }
 
-// Function to generate a rainbow table from a list of passwords
-pub fn gen_rainbow_table(
-    in_file: &str,
-    out_file: &str,
+// Where the chains of a new rainbow table start from
+pub enum ChainStarts<'a> {
+    // Seed passwords read from a file, one per line, stored verbatim in the table
+    SeedFile(&'a str),
+    // `count` start points derived from the chain index and `table_seed`
+    Indexed { count: u64, table_seed: u64 },
+}
+
+// Walks one chain from `start` and returns its end point and number of links
+// Without dp_bits the chain is always num_links long; with dp_bits it stops at the first
+// password whose hash is distinguished, and None means it did not get there within num_links
+fn walk_chain(
+    start: &str,
     algorithm: &str,
+    min_len: usize,
+    max_len: usize,
+    allowed: &[u8],
     num_links: usize,
-    threads: usize,
-) -> Result<(), Box<dyn std::error::Error>> {
-    // Open the input file containing seed passwords
-    let file = File::open(in_file)?;
-    let reader = BufReader::new(file);
-    let seeds: Vec<String> = reader.lines().map_while(Result::ok).collect();
-
-    if seeds.is_empty() {
-        return Err("No seeds found".into());
+    dp_bits: Option<u8>,
+) -> Option<(String, usize)> {
+    let mut pwd = start.to_string();
+    let mut hash = compute_hash(&pwd, algorithm).ok()?;
+    for link in 1..=num_links {
+        pwd = reduce_hash(&hash, min_len, max_len, allowed);
+        hash = compute_hash(&pwd, algorithm).ok()?;
+        if dp_bits.is_some_and(|bits| is_distinguished(&hash, bits)) {
+            return Some((pwd, link));
+        }
     }
-
-    let pwd_len = seeds[0].len();
-    // Check that all passwords have the same length as the first one
-    if !seeds.iter().all(|pwd| pwd.len() == pwd_len) {
-        return Err("Input file contains passwords of varying lengths".into());
+    match dp_bits {
+        Some(_) => None,
+        None => Some((pwd, num_links)),
     }
+}
 
-    // Define the allowed character set (ASCII printable characters)
-    let allowed: Vec<u8> = (32u8..=126u8).collect();
-
-    // Shared vector to store the start and end of each chain
-    let chains = Arc::new(Mutex::new(Vec::new()));
+// Settings for building a rainbow table
+#[derive(Debug, Clone)]
+pub struct TableParams {
+    pub algorithm: String,
+    // Links per chain, or the longest allowed chain in distinguished point mode
+    pub num_links: usize,
+    pub threads: usize,
+    // Password length range, defaults to the seed lengths
+    pub len_range: Option<(usize, usize)>,
+    // Prefix compress the sorted end points
+    pub compress: bool,
+    // End chains at hashes with this many leading zero bits instead of after num_links
+    pub dp_bits: Option<u8>,
+    // Continue from the checkpoint of an interrupted run
+    pub resume: bool,
+    // Chains between two checkpoints
+    pub checkpoint_every: usize,
+}
 
-    // Determine the number of seeds each thread should process
-    let chunk_size = seeds.len().div_ceil(threads);
+// Everything the worker threads need to walk chains for one table
+struct ChainJob {
+    seeds: Vec<String>,
+    algorithm: String,
+    min_len: usize,
+    max_len: usize,
+    allowed: Vec<u8>,
+    num_links: usize,
+    dp_bits: Option<u8>,
+    flags: u8,
+    table_seed: u64,
+}
 
-    let mut handles = Vec::new();
-    for chunk in seeds.chunks(chunk_size) {
-        let chunk = chunk.to_vec();
-        let algo = algorithm.to_string();
-        let chains = Arc::clone(&chains);
-        let allowed = allowed.clone();
+impl ChainJob {
+    // Start password of the chain with this index
+    fn start(&self, index: u64) -> String {
+        if self.flags & FLAG_INDEXED_STARTS != 0 {
+            index_start(
+                self.table_seed,
+                index,
+                self.min_len,
+                self.max_len,
+                &self.allowed,
+            )
+        } else {
+            self.seeds[index as usize].clone()
+        }
+    }
 
-        // Spawn a new thread for each chunk
-        let handle = thread::spawn(move || {
-            let mut local = Vec::new();
-            for mut pwd in chunk {
-                let start = pwd.clone();
-                // Apply hash and reduction functions num_links times
-                for _ in 0..num_links {
-                    if let Ok(hash) = compute_hash(&pwd, &algo) {
-                        pwd = reduce_hash(&hash, pwd.len(), &allowed);
+    // Walks chains first..last across threads and returns (index, end, links) in index order
+    fn walk_range(
+        self: &Arc<Self>,
+        first: u64,
+        last: u64,
+        threads: usize,
+        tracker: &Arc<Tracker>,
+    ) -> Result<Vec<WalkedChain>, Box<dyn std::error::Error>> {
+        // Determine the number of chains each thread should process
+        let chunk_size = (last - first).div_ceil(threads.max(1) as u64);
+
+        let mut handles = Vec::new();
+        let mut from = first;
+        while from < last {
+            let to = (from + chunk_size).min(last);
+            let job = Arc::clone(self);
+            let tracker = Arc::clone(tracker);
+
+            // Spawn a new thread for each chunk
+            handles.push(thread::spawn(move || {
+                let mut local = Vec::new();
+                for index in from..to {
+                    if tracker.cancelled() {
+                        break;
                     }
+                    let start = job.start(index);
+                    // Chains that never hit a distinguished point are dropped
+                    if let Some((end, links)) = walk_chain(
+                        &start,
+                        &job.algorithm,
+                        job.min_len,
+                        job.max_len,
+                        &job.allowed,
+                        job.num_links,
+                        job.dp_bits,
+                    ) {
+                        local.push((index, end, links));
+                    }
+                    tracker.advance(1);
                 }
-                // Store the start and end of the chain
-                local.push((start, pwd));
-            }
-            // Safely add our chains to the shared list
-            if let Ok(mut c) = chains.lock() {
-                c.extend(local);
-            }
-        });
+                local
+            }));
+            from = to;
+        }
 
-        handles.push(handle);
+        // Collect the chunks in order so the partial file stays sorted by index
+        let mut chains = Vec::new();
+        for handle in handles {
+            chains.extend(handle.join().map_err(|_| "Chain worker thread panicked")?);
+        }
+        Ok(chains)
     }
+}
 
-    // Wait for all threads to finish
-    for handle in handles {
-        let _ = handle.join();
+// Function to generate a rainbow table from a list of passwords or index-derived starts
+// In distinguished point mode chains that grow past num_links are dropped and
+// chains that merge into the same end point are deduplicated, keeping the longest
+pub fn gen_rainbow_table(
+    starts: ChainStarts,
+    out_file: &str,
+    params: &TableParams,
+    hooks: &Hooks,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let algorithm = params.algorithm.as_str();
+    let num_links = params.num_links;
+    let len_range = params.len_range;
+    let dp_bits = params.dp_bits;
+    if num_links == 0 {
+        return Err("Number of links must be greater than zero".into());
+    }
+    if let Some(bits) = dp_bits
+        && (bits == 0 || bits as usize > 8 * digest_len(algorithm)?)
+    {
+        return Err("Distinguished point bits must fit inside the digest".into());
     }
 
-    // Get all chains back from shared state
-    let chains = Arc::try_unwrap(chains)
-        .map_err(|_| "Arc had multiple strong references".to_string())?
-        .into_inner()
-        .map_err(|e| Box::new(e) as Box<dyn std::error::Error>)?;
+    // Define the allowed character set (ASCII printable characters)
+    let allowed: Vec<u8> = (32u8..=126u8).collect();
 
-    // Write the chains to the output file
-    let mut f = File::create(out_file)?;
-    f.write_all(b"rainbowtable")?;
-    f.write_all(&[VERSION])?;
-    f.write_all(&[algorithm.len() as u8])?;
-    f.write_all(algorithm.as_bytes())?;
-    f.write_all(&[pwd_len as u8])?;
-    f.write_all(&(allowed.len() as u128).to_be_bytes())?;
-    f.write_all(&(num_links as u128).to_be_bytes())?;
-    f.write_all(&[32u8])?;
+    let (seeds, min_len, max_len, flags, table_seed, count) = match starts {
+        ChainStarts::SeedFile(in_file) => {
+            // Open the input file containing seed passwords
+            let file = File::open(in_file)?;
+            let reader = BufReader::new(file);
+            let seeds: Vec<String> = reader.lines().map_while(Result::ok).collect();
 
-    // Write each chain's start and end passwords
-    for (start, end) in chains {
-        f.write_all(start.as_bytes())?;
-        f.write_all(end.as_bytes())?;
+            if seeds.is_empty() {
+                return Err("No seeds found".into());
+            }
+
+            // Work out the length range, either given or taken from the seeds
+            let seed_min = seeds.iter().map(|pwd| pwd.len()).min().unwrap_or(0);
+            let seed_max = seeds.iter().map(|pwd| pwd.len()).max().unwrap_or(0);
+            let (min_len, max_len) = len_range.unwrap_or((seed_min, seed_max));
+            // Check that every seed fits in the range
+            if seed_min < min_len || seed_max > max_len {
+                return Err(format!(
+                    "Input file contains passwords outside the length range {}..={}",
+                    min_len, max_len
+                )
+                .into());
+            }
+            let count = seeds.len() as u64;
+            (seeds, min_len, max_len, 0, 0, count)
+        }
+        ChainStarts::Indexed { count, table_seed } => {
+            if count == 0 {
+                return Err("Number of chains must be greater than zero".into());
+            }
+            let (min_len, max_len) =
+                len_range.ok_or("A password length range is required without a seed file")?;
+            (
+                Vec::new(),
+                min_len,
+                max_len,
+                FLAG_INDEXED_STARTS,
+                table_seed,
+                count,
+            )
+        }
+    };
+    if min_len == 0 || min_len > max_len || max_len > u8::MAX as usize {
+        return Err(format!("Invalid password length range {}..={}", min_len, max_len).into());
+    }
+    // Everything that decides the chains, so a resume never mixes two different tables
+    let fingerprint = format!(
+        "{}/{}/{}-{}/{}/{}/{}/{}/{:08x}",
+        algorithm,
+        num_links,
+        min_len,
+        max_len,
+        dp_bits.unwrap_or(0),
+        flags,
+        table_seed,
+        count,
+        crc32fast::hash(seeds.join("\n").as_bytes())
+    );
+    let job = Arc::new(ChainJob {
+        seeds,
+        algorithm: algorithm.to_string(),
+        min_len,
+        max_len,
+        allowed: allowed.clone(),
+        num_links,
+        dp_bits,
+        flags,
+        table_seed,
+    });
+
+    // Finished chains are appended to a partial file and the checkpoint says how far we got
+    let partial_path = format!("{}.partial", out_file);
+    let checkpoint_path = format!("{}.checkpoint", out_file);
+    let resumed = if params.resume {
+        Checkpoint::load(&checkpoint_path)?
+    } else {
+        None
+    };
+    match &resumed {
+        Some(checkpoint) if checkpoint.fingerprint != fingerprint => {
+            return Err(format!(
+                "Checkpoint '{}' was made with different settings",
+                checkpoint_path
+            )
+            .into());
+        }
+        Some(checkpoint) => info!(
+            "Resuming from checkpoint, {} of {} chains already done",
+            checkpoint.processed, count
+        ),
+        None if params.resume => info!("No checkpoint found, starting from scratch"),
+        None => {}
+    }
+    if resumed.is_none() && std::path::Path::new(&checkpoint_path).exists() {
+        std::fs::remove_file(&checkpoint_path)?;
+    }
+    let mut partial = open_partial(&partial_path, resumed.as_ref())?;
+    let mut processed = resumed.map_or(0, |checkpoint| checkpoint.processed);
+
+    let tracker = Tracker::new(hooks, count, processed);
+    let batch = params.checkpoint_every.max(1) as u64;
+    while processed < count {
+        let last = (processed + batch).min(count);
+        let walked = job.walk_range(processed, last, params.threads, &tracker)?;
+        // A cancelled batch is incomplete, so only the chains up to the last checkpoint are kept
+        if tracker.cancelled() {
+            return Err(format!(
+                "Cancelled after {} of {} chains, rerun with --resume to continue",
+                processed, count
+            )
+            .into());
+        }
+        let partial_len = append_chains(&mut partial, &walked)?;
+        processed = last;
+        Checkpoint {
+            fingerprint: fingerprint.clone(),
+            processed,
+            partial_len,
+        }
+        .save(&checkpoint_path)?;
+        info!("Checkpoint: {} of {} chains done", processed, count);
+    }
+    drop(partial);
+
+    // Rebuild the start of every finished chain from its index
+    let mut chains: Vec<(u64, String, String, usize)> = read_partial(&partial_path)?
+        .into_iter()
+        .map(|(index, end, links)| (index, job.start(index), end, links))
+        .collect();
+
+    let mut flags = flags;
+    if dp_bits.is_some() {
+        flags |= FLAG_DISTINGUISHED;
+        // Chains that merged end at the same point, only the longest one is worth keeping
+        let reached = chains.len();
+        chains.sort_by(|a, b| a.2.cmp(&b.2).then(b.3.cmp(&a.3)));
+        chains.dedup_by(|a, b| a.2 == b.2);
+        info!(
+            "Kept {} of {} distinguished point chains ({} too long, {} merged)",
+            chains.len(),
+            count,
+            count as usize - reached,
+            reached - chains.len()
+        );
+        if chains.is_empty() {
+            return Err("No chain reached a distinguished point, raise --num-links".into());
+        }
     }
+    let chains: Vec<ChainRecord> = chains
+        .into_iter()
+        .map(|(index, start, end, _)| (index, start, end))
+        .collect();
+
+    let charset_size = allowed.len() as u128;
+    // Index and point widths are filled in by the writer
+    let header = TableHeader {
+        version: TABLE_VERSION,
+        algorithm: algorithm.to_string(),
+        min_len,
+        max_len,
+        charset_size,
+        num_links,
+        ascii_offset: 32,
+        flags: if params.compress {
+            flags | FLAG_PREFIX_COMPRESSED
+        } else {
+            flags
+        },
+        table_seed,
+        index_bytes: 0,
+        point_bytes: 0,
+        dp_bits: dp_bits.unwrap_or(0),
+        body_len: 0,
+        body_crc: 0,
+    };
+
+    // Write the header and the packed chains to the output file
+    RainbowTable { header, chains }.write(out_file)?;
 
+    // The table is complete, the partial chains and checkpoint are no longer needed
+    std::fs::remove_file(&partial_path)?;
+    std::fs::remove_file(&checkpoint_path)?;
     Ok(())
 }
 
@@ -318,116 +691,136 @@ pub fn gen_rainbow_table(
 pub fn dump_rainbow_table(in_file: &str) -> Result<(), Box<dyn std::error::Error>> {
     // Read the entire file into memory
     let data = std::fs::read(in_file)?;
-    // Verify the magic header
-    if &data[..12] != b"rainbowtable" {
-        return Err("Invalid rainbow table format".into());
-    }
-
-    let mut pos = 12;
-    let version = data[pos];
-    pos += 1;
-    let algo_len = data[pos] as usize;
-    pos += 1;
-    let algo = String::from_utf8(data[pos..pos + algo_len].to_vec())?;
-    pos += algo_len;
-    let pwd_len = data[pos];
-    pos += 1;
-    let charset_size = u128::from_be_bytes(data[pos..pos + 16].try_into()?);
-    pos += 16;
-    let num_links = u128::from_be_bytes(data[pos..pos + 16].try_into()?);
-    pos += 16;
-    let ascii_offset = data[pos];
-    pos += 1;
+    let (header, pos) = TableHeader::parse(&data)?;
 
     // Print table metadata
     println!("Hashassin Rainbow Table");
-    println!("VERSION: {version}");
-    println!("ALGORITHM: {algo}");
-    println!("PASSWORD LENGTH: {pwd_len}");
-    println!("KEY SIZE: {charset_size}");
-    println!("NUM LINKS: {num_links}");
-    println!("ASCII OFFSET: {ascii_offset}");
+    println!("VERSION: {}", header.version);
+    println!("ALGORITHM: {}", header.algorithm);
+    if header.min_len == header.max_len {
+        println!("PASSWORD LENGTH: {}", header.min_len);
+    } else {
+        println!("PASSWORD LENGTH: {}-{}", header.min_len, header.max_len);
+    }
+    println!("KEY SIZE: {}", header.charset_size);
+    println!("NUM LINKS: {}", header.num_links);
+    println!("ASCII OFFSET: {}", header.ascii_offset);
+    if header.indexed_starts() {
+        println!("TABLE SEED: {}", header.table_seed);
+    }
+    if header.flags & FLAG_PREFIX_COMPRESSED != 0 {
+        println!("PREFIX COMPRESSED: yes");
+    }
+    if header.distinguished() {
+        println!("DISTINGUISHED POINT BITS: {}", header.dp_bits);
+    }
 
     // Iterate over each chain and print start and end passwords
-    let chain_len = (pwd_len as usize) * 2;
-    while pos + chain_len <= data.len() {
-        println!(
-            "{}\t{}",
-            String::from_utf8_lossy(&data[pos..pos + pwd_len as usize]),
-            String::from_utf8_lossy(&data[pos + pwd_len as usize..pos + chain_len])
-        );
-        pos += chain_len;
+    for (start, end) in header.read_chains(&data, pos)? {
+        println!("{}\t{}", start, end);
     }
 
     Ok(())
 }
 
-// Function to crack hashes using a rainbow table
-pub fn crack(
-    table_file: &str,
-    hashes_file: &str,
-    out_file: Option<&str>,
-    _threads: usize,
+// Looks a single hash up in the chains of a table (keyed by end point)
+fn lookup_hash(
+    target_hash: &[u8],
+    chains: &std::collections::HashMap<String, String>,
+    header: &TableHeader,
+    allowed: &[u8],
+) -> Result<Option<String>, Box<dyn std::error::Error>> {
+    let algorithm = header.algorithm.as_str();
+    let (min_len, max_len) = (header.min_len, header.max_len);
+    let num_links = header.num_links;
+
+    if header.distinguished() {
+        // Walk forward until the first distinguished point, which is the only end to check
+        let mut current_hash = target_hash.to_vec();
+        for _ in 0..num_links {
+            let current_pwd = reduce_hash(&current_hash, min_len, max_len, allowed);
+            current_hash = compute_hash(&current_pwd, algorithm)?;
+            if !is_distinguished(&current_hash, header.dp_bits) {
+                continue;
+            }
+            let Some(start_pwd) = chains.get(&current_pwd) else {
+                return Ok(None);
+            };
+            // Replay the chain from its start until we hit the target or its end
+            let mut candidate = start_pwd.clone();
+            for _ in 0..=num_links {
+                let candidate_hash = compute_hash(&candidate, algorithm)?;
+                if candidate_hash == target_hash {
+                    return Ok(Some(candidate));
+                }
+                if candidate == current_pwd {
+                    break;
+                }
+                candidate = reduce_hash(&candidate_hash, min_len, max_len, allowed);
+            }
+            return Ok(None);
+        }
+        return Ok(None);
+    }
+
+    for i in 0..num_links {
+        let mut current_hash = target_hash.to_vec();
+        let mut current_pwd = reduce_hash(&current_hash, min_len, max_len, allowed);
+
+        for _ in 0..(num_links - i - 1) {
+            current_hash = compute_hash(&current_pwd, algorithm)?;
+            current_pwd = reduce_hash(&current_hash, min_len, max_len, allowed);
+        }
+
+        if let Some(start_pwd) = chains.get(&current_pwd) {
+            let mut candidate = start_pwd.clone();
+
+            for _ in 0..=i {
+                let candidate_hash = compute_hash(&candidate, algorithm)?;
+
+                if candidate_hash == target_hash {
+                    return Ok(Some(candidate));
+                }
+
+                candidate = reduce_hash(&candidate_hash, min_len, max_len, allowed);
+            }
+        }
+    }
+
+    Ok(None)
+}
+
+// A cracked hash as (hex digest, password), the password is None when the table missed it
+pub type Cracked = (String, Option<String>);
+
+// Cracks the hashes against a table held in memory, such as a mapped file
+// Returns one entry per hash in the order of the hash file, a cancelled crack stops after
+// the hash it is on and returns the entries up to there
+pub fn crack_table(
+    table_data: &[u8],
+    hash_file: &HashFile,
     cache: Option<&Cache<String, String>>,
-) -> Result<(), Box<dyn std::error::Error>> {
+    hooks: &Hooks,
+) -> Result<Vec<Cracked>, Box<dyn std::error::Error>> {
     use std::collections::HashMap;
-    use std::fs::File;
-    use std::io::Write;
-    use tracing::info;
 
-    info!("Reading rainbow table and hashes files");
-    let table_data = std::fs::read(table_file)?;
-    let hashes_data = std::fs::read(hashes_file)?;
-
-    if &table_data[..12] != b"rainbowtable" {
-        return Err("Invalid rainbow table format".into());
-    }
-
-    let mut pos = 12;
-    let _version = table_data[pos];
-    pos += 1;
-    let algo_len = table_data[pos] as usize;
-    pos += 1;
-    let algorithm = String::from_utf8(table_data[pos..pos + algo_len].to_vec())?;
-    pos += algo_len;
-    let pwd_len = table_data[pos] as usize;
-    pos += 1;
-    let _charset_size = u128::from_be_bytes(table_data[pos..pos + 16].try_into()?);
-    pos += 16;
-    let num_links = u128::from_be_bytes(table_data[pos..pos + 16].try_into()?) as usize;
-    pos += 16;
-    let _ascii_offset = table_data[pos];
-    pos += 1;
+    let (header, pos) = TableHeader::parse(table_data)?;
+    header.verify_body(table_data, pos)?;
+    let algorithm = header.algorithm.clone();
+    let num_links = header.num_links;
+    let (min_len, max_len) = (header.min_len, header.max_len);
 
     info!(
-        "Rainbow table: {} algorithm, {} password length, {} chain links",
-        algorithm, pwd_len, num_links
+        "Rainbow table: {} algorithm, {}-{} password length, {} chain links",
+        algorithm, min_len, max_len, num_links
     );
 
-    let mut chains = HashMap::new();
-    let chain_len = pwd_len * 2;
-    while pos + chain_len <= table_data.len() {
-        let start_pwd = String::from_utf8_lossy(&table_data[pos..pos + pwd_len]).to_string();
-        let end_pwd =
-            String::from_utf8_lossy(&table_data[pos + pwd_len..pos + chain_len]).to_string();
-        chains.insert(end_pwd, start_pwd);
-        pos += chain_len;
+    // Both v1 and v2 hash files are accepted
+    let algo_hash = &hash_file.algorithm;
+    if hash_file.is_salted() {
+        return Err("Salted hashes cannot be cracked with a rainbow table".into());
     }
 
-    info!("Loaded {} chains", chains.len());
-
-    let version_hash = hashes_data[0];
-    if version_hash != VERSION {
-        return Err(format!(
-            "Hash file version mismatch: expected {}, got {}",
-            VERSION, version_hash
-        )
-        .into());
-    }
-
-    let algo_len_hash = hashes_data[1] as usize;
-    let algo_hash = String::from_utf8(hashes_data[2..2 + algo_len_hash].to_vec())?;
-
     if algo_hash.to_lowercase() != algorithm.to_lowercase() {
         return Err(format!(
             "Algorithm mismatch: table uses {}, hashes use {}",
@@ -436,94 +829,98 @@ pub fn crack(
         .into());
     }
 
-    let hash_len = match algorithm.as_str() {
-        "md5" => 16,
-        "sha256" => 32,
-        "sha3_512" => 64,
-        "scrypt" => 32,
-        _ => return Err(format!("Unsupported algorithm: {}", algorithm).into()),
-    };
+    let mut chains = HashMap::new();
+    for (start_pwd, end_pwd) in header.read_chains(table_data, pos)? {
+        chains.insert(end_pwd, start_pwd);
+    }
+
+    info!("Loaded {} chains", chains.len());
 
     let allowed: Vec<u8> = (32u8..=126u8).collect();
-    let mut hash_pos = 2 + algo_len_hash + 1;
     let mut found_count = 0;
-    let mut total_count = 0;
-    let mut output_lines = Vec::new();
+    let mut results = Vec::new();
+    let tracker = Tracker::new(hooks, hash_file.records.len() as u64, 0);
+    // Hashes of the end points of a distinguished point table, worked out once the first
+    // distinguished target turns up
+    let mut end_hashes: Option<HashMap<Vec<u8>, String>> = None;
 
     info!("Starting to crack");
 
-    while hash_pos + hash_len <= hashes_data.len() {
-        total_count += 1;
-        let target_hash = &hashes_data[hash_pos..hash_pos + hash_len];
+    for (_, target_hash) in &hash_file.records {
+        if tracker.cancelled() {
+            break;
+        }
+        tracker.advance(1);
         let hash_hex = hex::encode(target_hash);
 
-        if let Some(cache_ref) = cache {
-            if let Some(entry) = cache_ref.get(&hash_hex) {
-                output_lines.push(format!("{}\t{}", hash_hex, entry.value()));
-                found_count += 1;
-                hash_pos += hash_len;
-                continue;
+        if let Some(entry) = cache.and_then(|cache_ref| cache_ref.get(&hash_hex)) {
+            if let Some(sink) = &hooks.results {
+                sink.result(&hash_hex, Some(entry.value()));
             }
+            results.push((hash_hex, Some(entry.value().clone())));
+            found_count += 1;
+            continue;
         }
 
-        let mut found = false;
-
-        for i in 0..num_links {
-            let mut current_hash = target_hash.to_vec();
-            let mut current_pwd = reduce_hash(&current_hash, pwd_len, &allowed);
-
-            for _ in 0..(num_links - i - 1) {
-                current_hash = compute_hash(&current_pwd, &algorithm)?;
-                current_pwd = reduce_hash(&current_hash, pwd_len, &allowed);
-            }
-
-            if let Some(start_pwd) = chains.get(&current_pwd) {
-                let mut candidate = start_pwd.clone();
-
-                for _ in 0..=i {
-                    let candidate_hash = compute_hash(&candidate, &algorithm)?;
-
-                    if candidate_hash == target_hash {
-                        output_lines.push(format!("{}\t{}", hash_hex, candidate));
-                        found_count += 1;
-
-                        if let Some(cache_ref) = cache {
-                            let _ = cache_ref.insert(
-                                hash_hex.clone(),
-                                candidate.clone(),
-                                candidate.len() as i64,
-                            );
-                        }
-
-                        found = true;
-                        break;
-                    }
-
-                    candidate = reduce_hash(&candidate_hash, pwd_len, &allowed);
-                }
-
-                if found {
-                    break;
+        // A distinguished hash may be that of an end point, which no walk from it reaches
+        let mut found = None;
+        if header.distinguished() && is_distinguished(target_hash, header.dp_bits) {
+            if end_hashes.is_none() {
+                let mut hashes = HashMap::with_capacity(chains.len());
+                for end in chains.keys() {
+                    hashes.insert(compute_hash(end, &algorithm)?, end.clone());
                 }
+                end_hashes = Some(hashes);
             }
+            found = end_hashes
+                .as_ref()
+                .and_then(|hashes| hashes.get(target_hash))
+                .cloned();
         }
-
-        if !found {
-            output_lines.push(format!("{}\tNOT FOUND", hash_hex));
+        let found = match found {
+            Some(end) => Some(end),
+            None => lookup_hash(target_hash, &chains, &header, &allowed)?,
+        };
+        if let Some(sink) = &hooks.results {
+            sink.result(&hash_hex, found.as_deref());
         }
-
-        hash_pos += hash_len;
+        if let (Some(candidate), Some(cache_ref)) = (&found, cache) {
+            let _ = cache_ref.insert(hash_hex.clone(), candidate.clone(), candidate.len() as i64);
+        }
+        found_count += found.is_some() as usize;
+        results.push((hash_hex, found));
     }
 
     info!(
         "Cracking complete: found {}/{} passwords",
-        found_count, total_count
+        found_count,
+        results.len()
     );
+    Ok(results)
+}
 
-    if found_count == 0 {
+// Function to crack hashes using a rainbow table
+pub fn crack(
+    table_file: &str,
+    hashes_file: &str,
+    out_file: Option<&str>,
+    _threads: usize,
+    cache: Option<&Cache<String, String>>,
+    hooks: &Hooks,
+) -> Result<(), Box<dyn std::error::Error>> {
+    info!("Reading rainbow table and hashes files");
+    let table_data = std::fs::read(table_file)?;
+    let hash_file = HashFile::from_bytes(&std::fs::read(hashes_file)?)?;
+
+    let results = crack_table(&table_data, &hash_file, cache, hooks)?;
+    let cancelled = hooks.cancel.is_cancelled();
+    if results.iter().all(|(_, found)| found.is_none()) && !cancelled {
         return Err("No passwords found.".into());
     }
 
+    let output_lines = results
+        .iter()
+        .map(|(hash, found)| format!("{}\t{}", hash, found.as_deref().unwrap_or("NOT FOUND")));
     match out_file {
         Some(path) => {
             let mut f = File::create(path)?;
@@ -538,5 +935,177 @@ pub fn crack(
         }
     }
 
+    if cancelled {
+        return Err(format!(
+            "Cancelled after {} of {} hashes, partial results written",
+            results.len(),
+            hash_file.records.len()
+        )
+        .into());
+    }
+
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn seeded_passwords_are_reproducible() -> Result<(), Box<dyn std::error::Error>> {
+        let first = gen_passwords(8, 1000, 4, Some(42))?;
+        let second = gen_passwords(8, 1000, 4, Some(42))?;
+        assert_eq!(first.concat().as_bytes(), second.concat().as_bytes());
+        assert_eq!(first.len(), 1000);
+
+        let other = gen_passwords(8, 1000, 4, Some(43))?;
+        assert_ne!(first, other);
+        Ok(())
+    }
+
+    // Cancels the run it is hooked into once the last chain is walked, so the last batch
+    // never reaches its checkpoint
+    struct CancelAtEnd(CancelToken);
+
+    impl Progress for CancelAtEnd {
+        fn update(&self, done: u64, total: u64, _rate: f64) {
+            if done == total {
+                self.0.cancel();
+            }
+        }
+    }
+
+    #[test]
+    fn resumed_generation_matches_a_straight_run() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = std::env::temp_dir().join(format!("hashassin-resume-{}", std::process::id()));
+        std::fs::create_dir_all(&dir)?;
+        let path = |file: &str| dir.join(file).to_string_lossy().to_string();
+        let starts = || ChainStarts::Indexed {
+            count: 100,
+            table_seed: 11,
+        };
+        let mut params = TableParams {
+            algorithm: "md5".to_string(),
+            num_links: 20,
+            threads: 2,
+            len_range: Some((3, 5)),
+            compress: false,
+            dp_bits: None,
+            resume: false,
+            checkpoint_every: 10,
+        };
+        gen_rainbow_table(
+            starts(),
+            &path("straight.rainbow"),
+            &params,
+            &Hooks::default(),
+        )?;
+
+        let out = path("resumed.rainbow");
+        let cancel = CancelToken::new();
+        let hooks = Hooks::new(Arc::new(CancelAtEnd(cancel.clone())), cancel);
+        assert!(gen_rainbow_table(starts(), &out, &params, &hooks).is_err());
+        let checkpoint =
+            Checkpoint::load(&format!("{}.checkpoint", out))?.ok_or("no checkpoint")?;
+        assert_eq!(checkpoint.processed, 90);
+        // Whatever a crash left behind the checkpoint is cut off again
+        std::fs::OpenOptions::new()
+            .append(true)
+            .open(format!("{}.partial", out))?
+            .write_all(b"half a chain")?;
+
+        // A resume with other settings would mix two tables
+        params.resume = true;
+        params.num_links = 21;
+        assert!(gen_rainbow_table(starts(), &out, &params, &Hooks::default()).is_err());
+
+        params.num_links = 20;
+        gen_rainbow_table(starts(), &out, &params, &Hooks::default())?;
+        assert_eq!(
+            std::fs::read(&out)?,
+            std::fs::read(path("straight.rainbow"))?
+        );
+        assert!(!std::path::Path::new(&format!("{}.checkpoint", out)).exists());
+        assert!(!std::path::Path::new(&format!("{}.partial", out)).exists());
+        std::fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+
+    // Every password of every chain in a table whose hash the table covers
+    // Rainbow chains cover the hashes of their start up to the password before the end,
+    // distinguished point chains also cover the hash of their end
+    fn chain_passwords(table: &RainbowTable) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+        let header = &table.header;
+        let allowed = header.charset();
+        let dp_bits = header.distinguished().then_some(header.dp_bits);
+        let walk = |start: &str, links: usize, dp_bits: Option<u8>| {
+            walk_chain(
+                start,
+                "md5",
+                header.min_len,
+                header.max_len,
+                &allowed,
+                links,
+                dp_bits,
+            )
+            .ok_or("chain walk failed")
+        };
+        let mut passwords = Vec::new();
+        for (_, start, end) in &table.chains {
+            let links = match dp_bits {
+                Some(_) => walk(start, header.num_links, dp_bits)?.1 + 1,
+                None => header.num_links,
+            };
+            for link in 0..links {
+                passwords.push(walk(start, link, None)?.0);
+            }
+            if dp_bits.is_some() {
+                assert_eq!(passwords.last(), Some(end));
+            }
+        }
+        Ok(passwords)
+    }
+
+    #[test]
+    fn every_password_inside_the_chains_cracks() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = std::env::temp_dir().join(format!("hashassin-lookup-{}", std::process::id()));
+        std::fs::create_dir_all(&dir)?;
+        for dp_bits in [None, Some(3)] {
+            let out = dir.join("t.rainbow").to_string_lossy().to_string();
+            let params = TableParams {
+                algorithm: "md5".to_string(),
+                num_links: if dp_bits.is_some() { 60 } else { 15 },
+                threads: 1,
+                len_range: Some((3, 4)),
+                compress: false,
+                dp_bits,
+                resume: false,
+                checkpoint_every: 1000,
+            };
+            let starts = ChainStarts::Indexed {
+                count: 40,
+                table_seed: 21,
+            };
+            gen_rainbow_table(starts, &out, &params, &Hooks::default())?;
+            let data = std::fs::read(&out)?;
+            let passwords = chain_passwords(&RainbowTable::from_bytes(&data)?)?;
+            let hashes = passwords
+                .iter()
+                .map(|password| compute_hash(password, "md5"))
+                .collect::<Result<Vec<_>, _>>()?;
+            if dp_bits.is_some() {
+                // The ends are what the walk used to miss
+                assert!(hashes.iter().any(|hash| is_distinguished(hash, 3)));
+            }
+
+            let hash_file = HashFile::new("md5", 0, hashes.clone())?;
+            let results = crack_table(&data, &hash_file, None, &Hooks::default())?;
+            for (hash, (_, found)) in hashes.iter().zip(&results) {
+                let found = found.as_deref().ok_or("a chain password was not found")?;
+                assert_eq!(&compute_hash(found, "md5")?, hash);
+            }
+        }
+        std::fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+}
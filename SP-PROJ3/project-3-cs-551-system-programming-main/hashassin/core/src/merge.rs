@@ -0,0 +1,350 @@
+// Combining tables built on different machines and breaking big tables apart
+
+use crate::{
+    ChainRecord, FLAG_INDEXED_STARTS, RainbowTable, TABLE_VERSION, TableHeader, walk_chain,
+};
+use std::collections::{BTreeMap, HashMap};
+use tracing::info;
+
+// How split_table divides the chains
+#[derive(Debug, Clone, Copy)]
+pub enum SplitBy {
+    // At most this many chains per output table
+    ChainCount(usize),
+    // One output table per distinct leading `n` characters of the end point
+    EndPrefix(usize),
+}
+
+// Merges tables with the same algorithm, charset, length range and links into one
+// Duplicate end points are dropped, and the result is written sorted in the newest format
+// Distinguished point tables keep the longest of the chains sharing an end point, like
+// gen_rainbow_table does, other tables keep the first
+// Returns the number of chains read and the number written
+pub fn merge_tables(
+    in_files: &[&str],
+    out_file: &str,
+) -> Result<(usize, usize), Box<dyn std::error::Error>> {
+    let (first, rest) = in_files
+        .split_first()
+        .ok_or("At least one table is needed to merge")?;
+    let mut merged = RainbowTable::read(first)?;
+    merged.header.version = TABLE_VERSION;
+
+    let mut read = merged.chains.len();
+    let mut kept = Kept::default();
+    for chain in std::mem::take(&mut merged.chains) {
+        kept.add(&merged.header, &mut merged.chains, chain);
+    }
+
+    for path in rest {
+        let table = RainbowTable::read(path)?;
+        if !merged.header.compatible_with(&table.header) {
+            return Err(format!(
+                "Table '{}' does not match '{}' (algorithm, charset, length range, links and mode must agree)",
+                path, first
+            )
+            .into());
+        }
+        read += table.chains.len();
+
+        // Index-derived starts only survive when every table used the same seed
+        if merged.header.indexed_starts()
+            && (!table.header.indexed_starts()
+                || table.header.table_seed != merged.header.table_seed)
+        {
+            info!("Table seeds differ, storing chain starts explicitly");
+            merged.header.flags &= !FLAG_INDEXED_STARTS;
+        }
+
+        for chain in table.chains {
+            kept.add(&merged.header, &mut merged.chains, chain);
+        }
+    }
+
+    // Explicit starts are numbered by position, keep the numbers unique
+    if !merged.header.indexed_starts() {
+        for (i, chain) in merged.chains.iter_mut().enumerate() {
+            chain.0 = i as u64;
+        }
+    }
+
+    let written = merged.chains.len();
+    merged.write(out_file)?;
+    info!(
+        "Merged {} tables: {} chains read, {} written",
+        in_files.len(),
+        read,
+        written
+    );
+    Ok((read, written))
+}
+
+// The chain kept for each end point, by position in the merged chains
+// Chain lengths are only worked out for end points that more than one chain reaches
+#[derive(Default)]
+struct Kept {
+    by_end: HashMap<String, (usize, Option<usize>)>,
+}
+
+impl Kept {
+    fn add(&mut self, header: &TableHeader, chains: &mut Vec<ChainRecord>, chain: ChainRecord) {
+        let Some((position, links)) = self.by_end.get_mut(&chain.2) else {
+            self.by_end.insert(chain.2.clone(), (chains.len(), None));
+            chains.push(chain);
+            return;
+        };
+        if !header.distinguished() {
+            return;
+        }
+        let current = *links.get_or_insert_with(|| chain_links(header, &chains[*position].1));
+        let candidate = chain_links(header, &chain.1);
+        if candidate > current {
+            chains[*position] = chain;
+            *links = Some(candidate);
+        }
+    }
+}
+
+// Number of links from `start` to its distinguished point, 0 when it never gets there
+fn chain_links(header: &TableHeader, start: &str) -> usize {
+    walk_chain(
+        start,
+        &header.algorithm,
+        header.min_len,
+        header.max_len,
+        &header.charset(),
+        header.num_links,
+        Some(header.dp_bits),
+    )
+    .map_or(0, |(_, links)| links)
+}
+
+// Splits a table into several tables written as `<out_prefix>.<part>.rainbow`
+// Parts are numbered when splitting by count and named by the hex end point prefix otherwise
+// Returns the paths that were written
+pub fn split_table(
+    in_file: &str,
+    out_prefix: &str,
+    by: SplitBy,
+) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+    let mut table = RainbowTable::read(in_file)?;
+    table.header.version = TABLE_VERSION;
+    // Encoding sorts the chains by end point, so every part stays sorted
+    table.to_bytes()?;
+
+    let mut parts: BTreeMap<String, Vec<ChainRecord>> = BTreeMap::new();
+    match by {
+        SplitBy::ChainCount(0) | SplitBy::EndPrefix(0) => {
+            return Err("Split size must be greater than zero".into());
+        }
+        SplitBy::ChainCount(count) => {
+            let width = table.chains.len().div_ceil(count).to_string().len();
+            for (i, chunk) in table.chains.chunks(count).enumerate() {
+                parts.insert(format!("{:0width$}", i, width = width), chunk.to_vec());
+            }
+        }
+        SplitBy::EndPrefix(chars) => {
+            for chain in table.chains.drain(..) {
+                let prefix = hex::encode(&chain.2.as_bytes()[..chars.min(chain.2.len())]);
+                parts.entry(prefix).or_default().push(chain);
+            }
+        }
+    }
+
+    let mut written = Vec::new();
+    for (name, chains) in parts {
+        let path = format!("{}.{}.rainbow", out_prefix, name);
+        let mut part = RainbowTable {
+            header: table.header.clone(),
+            chains,
+        };
+        part.write(&path)?;
+        info!("Wrote {} chains to {}", part.chains.len(), path);
+        written.push(path);
+    }
+    Ok(written)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ChainStarts, FLAG_DISTINGUISHED, Hooks, TableParams, gen_rainbow_table};
+    use std::path::PathBuf;
+
+    type TestResult = Result<(), Box<dyn std::error::Error>>;
+
+    // A scratch directory for one test
+    fn scratch(name: &str) -> Result<PathBuf, Box<dyn std::error::Error>> {
+        let dir =
+            std::env::temp_dir().join(format!("hashassin-merge-{}-{}", std::process::id(), name));
+        std::fs::create_dir_all(&dir)?;
+        Ok(dir)
+    }
+
+    fn path(dir: &std::path::Path, file: &str) -> String {
+        dir.join(file).to_string_lossy().to_string()
+    }
+
+    // md5 over 4 character passwords, chains end at hashes with 4 leading zero bits
+    fn dp_header() -> TableHeader {
+        TableHeader {
+            version: TABLE_VERSION,
+            algorithm: "md5".to_string(),
+            min_len: 4,
+            max_len: 4,
+            charset_size: 95,
+            num_links: 200,
+            ascii_offset: 32,
+            flags: FLAG_DISTINGUISHED,
+            table_seed: 0,
+            index_bytes: 0,
+            point_bytes: 0,
+            dp_bits: 4,
+            body_len: 0,
+            body_crc: 0,
+        }
+    }
+
+    fn indexed_table(out_file: &str, table_seed: u64) -> TestResult {
+        let params = TableParams {
+            algorithm: "md5".to_string(),
+            num_links: 20,
+            threads: 1,
+            len_range: Some((4, 4)),
+            compress: false,
+            dp_bits: None,
+            resume: false,
+            checkpoint_every: 1000,
+        };
+        let starts = ChainStarts::Indexed {
+            count: 30,
+            table_seed,
+        };
+        gen_rainbow_table(starts, out_file, &params, &Hooks::default())
+    }
+
+    // Start and end of every chain, sorted
+    fn pairs(chains: &[ChainRecord]) -> Vec<(String, String)> {
+        let mut pairs: Vec<_> = chains
+            .iter()
+            .map(|(_, start, end)| (start.clone(), end.clone()))
+            .collect();
+        pairs.sort();
+        pairs
+    }
+
+    #[test]
+    fn dp_merge_keeps_the_longest_chain_per_end_point() -> TestResult {
+        let header = dp_header();
+        let allowed = header.charset();
+        let walk = |start: &str, links: usize, dp_bits: Option<u8>| {
+            walk_chain(start, "md5", 4, 4, &allowed, links, dp_bits)
+        };
+
+        // A chain of a few links, and a shorter one starting inside it at the same end
+        let (start, end, links) = (0..)
+            .map(|i| crate::index_start(5, i, 4, 4, &allowed))
+            .find_map(|start| {
+                let (end, links) = walk(&start, 200, Some(4))?;
+                (links >= 3).then_some((start, end, links))
+            })
+            .ok_or("no chain reached a distinguished point")?;
+        let (inner, _) = walk(&start, 1, None).ok_or("walk failed")?;
+        assert_eq!(walk(&inner, 200, Some(4)), Some((end.clone(), links - 1)));
+
+        let dir = scratch("dp")?;
+        let long = path(&dir, "long.rainbow");
+        let short = path(&dir, "short.rainbow");
+        let other = (0, "zzzz".to_string(), "aaaa".to_string());
+        RainbowTable {
+            header: header.clone(),
+            chains: vec![(0, start.clone(), end.clone())],
+        }
+        .write(&long)?;
+        RainbowTable {
+            header,
+            chains: vec![(0, inner.clone(), end.clone()), other.clone()],
+        }
+        .write(&short)?;
+
+        // Whichever table comes first, the longer chain wins
+        for order in [[&short, &long], [&long, &short]] {
+            let merged_path = path(&dir, "merged.rainbow");
+            let (read, written) = merge_tables(&[order[0], order[1]], &merged_path)?;
+            assert_eq!((read, written), (3, 2));
+            let merged = RainbowTable::read(&merged_path)?;
+            assert_eq!(
+                pairs(&merged.chains),
+                vec![
+                    (start.clone(), end.clone()),
+                    (other.1.clone(), other.2.clone())
+                ]
+            );
+        }
+        std::fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn merging_different_seeds_stores_starts_explicitly() -> TestResult {
+        let dir = scratch("seeds")?;
+        let (a, b, merged_path) = (
+            path(&dir, "a.rainbow"),
+            path(&dir, "b.rainbow"),
+            path(&dir, "m.rainbow"),
+        );
+        indexed_table(&a, 1)?;
+        indexed_table(&b, 2)?;
+        let (read, written) = merge_tables(&[&a, &b], &merged_path)?;
+        assert_eq!(read, 60);
+
+        let merged = RainbowTable::read(&merged_path)?;
+        assert!(!merged.header.indexed_starts());
+        assert_eq!(merged.chains.len(), written);
+        let allowed = merged.header.charset();
+        for (_, start, end) in &merged.chains {
+            let walked = walk_chain(start, "md5", 4, 4, &allowed, 20, None);
+            assert_eq!(walked.map(|(end, _)| end).as_ref(), Some(end));
+        }
+        // Both tables' chains survive, decoded from their own seeds
+        let mut expected = RainbowTable::read(&a)?.chains;
+        expected.extend(RainbowTable::read(&b)?.chains);
+        assert_eq!(pairs(&merged.chains), pairs(&expected));
+        std::fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn split_parts_cover_every_chain_once() -> TestResult {
+        let dir = scratch("split")?;
+        let table_path = path(&dir, "t.rainbow");
+        indexed_table(&table_path, 3)?;
+        let original = pairs(&RainbowTable::read(&table_path)?.chains);
+
+        for (by, prefix) in [
+            (SplitBy::ChainCount(7), "count"),
+            (SplitBy::EndPrefix(1), "end"),
+        ] {
+            let parts = split_table(&table_path, &path(&dir, prefix), by)?;
+            let mut chains = Vec::new();
+            for part in &parts {
+                let part_chains = RainbowTable::read(part)?.chains;
+                match by {
+                    SplitBy::ChainCount(count) => assert!(part_chains.len() <= count),
+                    SplitBy::EndPrefix(_) => {
+                        let first = &part_chains[0].2[..1];
+                        assert!(part_chains.iter().all(|chain| chain.2.starts_with(first)));
+                    }
+                }
+                chains.extend(part_chains);
+            }
+            if let SplitBy::ChainCount(count) = by {
+                assert_eq!(parts.len(), original.len().div_ceil(count));
+            }
+            assert_eq!(pairs(&chains), original);
+        }
+        assert!(split_table(&table_path, &path(&dir, "zero"), SplitBy::ChainCount(0)).is_err());
+        std::fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,140 @@
+// Progress reporting and cancellation for the long running operations
+//
+// gen_hashes, gen_rainbow_table and crack take a Hooks value. They report how many
+// items are done through the Progress callback and check the CancelToken between
+// items, so a caller can draw a progress bar or stop the work and keep what is done.
+// Updates are sent at most every REPORT_INTERVAL, plus one when the last item is done.
+// crack also hands every hash it has tried to the CrackSink, so results can be used
+// before the whole file is done.
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+// The shortest time between two progress updates
+const REPORT_INTERVAL: Duration = Duration::from_millis(100);
+
+// Receives progress updates, called from the worker threads
+pub trait Progress: Send + Sync {
+    // `done` of `total` items are finished, at `rate` items per second
+    fn update(&self, done: u64, total: u64, rate: f64);
+}
+
+// Receives crack results as they are found, `password` is None for a hash the table
+// does not cover
+pub trait CrackSink: Send + Sync {
+    fn result(&self, hash: &str, password: Option<&str>);
+}
+
+// Shared flag that asks a running operation to stop
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+}
+
+// Progress callback, cancel token and crack result sink handed to an operation, all optional
+#[derive(Clone, Default)]
+pub struct Hooks {
+    pub progress: Option<Arc<dyn Progress>>,
+    pub cancel: CancelToken,
+    pub results: Option<Arc<dyn CrackSink>>,
+}
+
+impl Hooks {
+    pub fn new(progress: Arc<dyn Progress>, cancel: CancelToken) -> Self {
+        Hooks {
+            progress: Some(progress),
+            cancel,
+            results: None,
+        }
+    }
+
+    pub fn with_results(mut self, results: Arc<dyn CrackSink>) -> Self {
+        self.results = Some(results);
+        self
+    }
+}
+
+// Counts finished items across threads and forwards them to the Progress callback
+pub(crate) struct Tracker {
+    hooks: Hooks,
+    total: u64,
+    done: AtomicU64,
+    // Items already done before this run, left out of the rate
+    initial: u64,
+    started: Instant,
+    // Nanoseconds after `started` of the last update, claimed by the thread sending it
+    reported: AtomicU64,
+}
+
+impl Tracker {
+    pub(crate) fn new(hooks: &Hooks, total: u64, done: u64) -> Arc<Self> {
+        let tracker = Arc::new(Tracker {
+            hooks: hooks.clone(),
+            total,
+            done: AtomicU64::new(done),
+            initial: done,
+            started: Instant::now(),
+            reported: AtomicU64::new(0),
+        });
+        tracker.send(done);
+        tracker
+    }
+
+    // Records `items` more finished items
+    pub(crate) fn advance(&self, items: u64) {
+        let done = self.done.fetch_add(items, Ordering::Relaxed) + items;
+        self.report(done);
+    }
+
+    pub(crate) fn cancelled(&self) -> bool {
+        self.hooks.cancel.is_cancelled()
+    }
+
+    pub(crate) fn done(&self) -> u64 {
+        self.done.load(Ordering::Relaxed)
+    }
+
+    // Sends an update unless one went out less than REPORT_INTERVAL ago, the last one
+    // always goes out
+    fn report(&self, done: u64) {
+        if self.hooks.progress.is_none() {
+            return;
+        }
+        let now = self.started.elapsed().as_nanos() as u64;
+        let last = self.reported.load(Ordering::Relaxed);
+        let due = now.saturating_sub(last) >= REPORT_INTERVAL.as_nanos() as u64
+            && self
+                .reported
+                .compare_exchange(last, now, Ordering::Relaxed, Ordering::Relaxed)
+                .is_ok();
+        if due || done >= self.total {
+            // Other threads may have moved on since `done` was counted
+            self.send(self.done().max(done));
+        }
+    }
+
+    fn send(&self, done: u64) {
+        if let Some(progress) = &self.hooks.progress {
+            let secs = self.started.elapsed().as_secs_f64();
+            let rate = if secs > 0.0 {
+                (done - self.initial) as f64 / secs
+            } else {
+                0.0
+            };
+            progress.update(done, self.total, rate);
+        }
+    }
+}
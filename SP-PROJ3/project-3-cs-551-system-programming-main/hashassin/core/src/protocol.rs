@@ -0,0 +1,209 @@
+// Response framing shared by hashassin_server and hashassin_client
+//
+// Every server reply is one frame:
+//
+//   "hsresp" | version | status | payload_len u64 | payload
+//
+// OK payloads carry the command's output, any other status carries an error message.
+// Table listings are carried as text, one TableInfo line per table. A v3 crack request is
+// answered with one More frame per hash, `hash<TAB>password` or `hash<TAB>NOT FOUND`, sent
+// as soon as the hash is cracked or every table has missed it, then an OK summary frame.
+//
+// Large tables are uploaded in chunks, each with its own CRC32, so a dropped connection
+// only costs the chunk in flight:
+//
+//   "upbegn" | version | flags | name_len | name | size u64 | file crc32  -> id u64 | offset u64
+//   "upchnk" | version | id u64 | offset u64 | len u32 | crc32 | data     -> offset u64
+//   "upoffs" | version | id u64                                           -> offset u64
+//   "updone" | version | id u64                                           -> message
+//
+// Beginning an upload again with the same name, size and file checksum resumes it.
+//
+// Crack jobs can also run detached from the connection that submitted them:
+//
+//   "submit" | version | crack v2 request body                            -> job id u64
+//   "status" | 2 | job id u64                                             -> status line, cracked lines
+//   "result" | version | job id u64                                       -> crack output
+//   "cancel" | version | job id u64                                       -> message
+//
+// Servers with a token file want every connection to authenticate before its first
+// command, see auth.rs:
+//
+//   "challg" | version                                                    -> nonce
+//   "auth\0\0" | version | HMAC-SHA256(token, nonce)                      -> scope name
+
+use crate::TableHeader;
+use std::fmt;
+
+// Magic bytes at the start of every response frame
+pub const RESPONSE_MAGIC: &[u8; 6] = b"hsresp";
+// Version of the framing written by this code
+pub const PROTOCOL_VERSION: u8 = 1;
+// Bytes before the payload: magic, version, status and payload length
+pub const RESPONSE_HEADER_LEN: usize = RESPONSE_MAGIC.len() + 1 + 1 + 8;
+// Upload request flag: overwrite a table that already has the name
+pub const UPLOAD_FLAG_REPLACE: u8 = 0x01;
+// Largest chunk a chunked upload may send at once
+pub const MAX_CHUNK_SIZE: u32 = 16 * 1024 * 1024;
+
+// Outcome of a request, sent as one byte
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    Ok = 0,
+    NotFound = 1,
+    BadRequest = 2,
+    TooLarge = 3,
+    InternalError = 4,
+    // A chunk that failed its checksum or was not at the upload's offset, resend from the
+    // offset the server reports
+    ChunkMismatch = 5,
+    // The job asked about has not finished yet
+    Pending = 6,
+    // One result of a streamed crack, more frames follow for the same request
+    More = 7,
+    // The server needs the connection to authenticate first, or authentication failed
+    Unauthorized = 8,
+    // The connection's token does not have the scope the command needs
+    Forbidden = 9,
+}
+
+impl Status {
+    pub fn from_u8(code: u8) -> Option<Status> {
+        match code {
+            0 => Some(Status::Ok),
+            1 => Some(Status::NotFound),
+            2 => Some(Status::BadRequest),
+            3 => Some(Status::TooLarge),
+            4 => Some(Status::InternalError),
+            5 => Some(Status::ChunkMismatch),
+            6 => Some(Status::Pending),
+            7 => Some(Status::More),
+            8 => Some(Status::Unauthorized),
+            9 => Some(Status::Forbidden),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Status::Ok => "OK",
+            Status::NotFound => "not found",
+            Status::BadRequest => "bad request",
+            Status::TooLarge => "too large",
+            Status::InternalError => "internal error",
+            Status::ChunkMismatch => "chunk mismatch",
+            Status::Pending => "pending",
+            Status::More => "more",
+            Status::Unauthorized => "unauthorized",
+            Status::Forbidden => "forbidden",
+        };
+        f.write_str(name)
+    }
+}
+
+// Builds a whole response frame
+pub fn encode_response(status: Status, payload: &[u8]) -> Vec<u8> {
+    let mut out = encode_response_header(status, payload.len() as u64);
+    out.extend_from_slice(payload);
+    out
+}
+
+// Builds the start of a frame, for payloads that are sent on their own afterwards
+pub fn encode_response_header(status: Status, payload_len: u64) -> Vec<u8> {
+    let mut out = Vec::with_capacity(RESPONSE_HEADER_LEN);
+    out.extend_from_slice(RESPONSE_MAGIC);
+    out.push(PROTOCOL_VERSION);
+    out.push(status as u8);
+    out.extend_from_slice(&payload_len.to_be_bytes());
+    out
+}
+
+// Parses the fixed-size start of a frame into its status and payload length
+pub fn decode_response_header(header: &[u8]) -> Result<(Status, u64), Box<dyn std::error::Error>> {
+    if header.len() < RESPONSE_HEADER_LEN || !header.starts_with(RESPONSE_MAGIC) {
+        return Err("Response is not a hashassin frame".into());
+    }
+    let pos = RESPONSE_MAGIC.len();
+    if header[pos] != PROTOCOL_VERSION {
+        return Err(format!("Unsupported protocol version: {}", header[pos]).into());
+    }
+    let status = Status::from_u8(header[pos + 1])
+        .ok_or_else(|| format!("Unknown response status: {}", header[pos + 1]))?;
+    let len = u64::from_be_bytes(header[pos + 2..pos + 10].try_into()?);
+    Ok((status, len))
+}
+
+// Checksum of an upload chunk
+pub fn chunk_crc(data: &[u8]) -> u32 {
+    crc32fast::hash(data)
+}
+
+// Summary of a table stored on a server, sent by the list and info commands as one line:
+//   name | version | algorithm | min_len-max_len | num_links | chains | size, tab separated
+#[derive(Debug, Clone, PartialEq)]
+pub struct TableInfo {
+    pub name: String,
+    pub version: u8,
+    pub algorithm: String,
+    pub min_len: usize,
+    pub max_len: usize,
+    pub num_links: usize,
+    pub chains: u64,
+    // Size of the table file in bytes
+    pub size: u64,
+}
+
+impl TableInfo {
+    // Summarises a table file, checking its checksums on the way
+    pub fn from_table(name: &str, data: &[u8]) -> Result<TableInfo, Box<dyn std::error::Error>> {
+        let (header, pos) = TableHeader::parse(data)?;
+        header.verify_body(data, pos)?;
+        let chains = header.count_chains(data, pos)? as u64;
+        Ok(TableInfo {
+            name: name.to_string(),
+            version: header.version,
+            algorithm: header.algorithm,
+            min_len: header.min_len,
+            max_len: header.max_len,
+            num_links: header.num_links,
+            chains,
+            size: data.len() as u64,
+        })
+    }
+
+    pub fn to_line(&self) -> String {
+        format!(
+            "{}\t{}\t{}\t{}-{}\t{}\t{}\t{}",
+            self.name,
+            self.version,
+            self.algorithm,
+            self.min_len,
+            self.max_len,
+            self.num_links,
+            self.chains,
+            self.size
+        )
+    }
+
+    pub fn from_line(line: &str) -> Result<TableInfo, Box<dyn std::error::Error>> {
+        let fields: Vec<&str> = line.split('\t').collect();
+        let [name, version, algorithm, lengths, num_links, chains, size] = fields[..] else {
+            return Err(format!("Malformed table info line: '{}'", line).into());
+        };
+        let (min_len, max_len) = lengths
+            .split_once('-')
+            .ok_or_else(|| format!("Malformed password lengths: '{}'", lengths))?;
+        Ok(TableInfo {
+            name: name.to_string(),
+            version: version.parse()?,
+            algorithm: algorithm.to_string(),
+            min_len: min_len.parse()?,
+            max_len: max_len.parse()?,
+            num_links: num_links.parse()?,
+            chains: chains.parse()?,
+            size: size.parse()?,
+        })
+    }
+}
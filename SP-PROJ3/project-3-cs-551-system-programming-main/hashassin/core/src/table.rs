@@ -0,0 +1,719 @@
+// The rainbow table file format: header, chain records and a reader/writer on top
+
+use crate::keyspace;
+use crate::{decode_password, encode_password, index_start};
+
+// Current version of the rainbow table format
+// v1 stores fixed-width chains, v2 adds a length range and length-prefixed chains,
+// v3 adds a flags byte so chains can start from index-derived passwords,
+// v4 packs passwords as keyspace indices and sorts chains by end point,
+// v5 ends the header with the body length and CRC32 checksums of the body and header
+pub const TABLE_VERSION: u8 = 5;
+
+// Table flag: chain starts are derived from the chain index and the table seed
+pub const FLAG_INDEXED_STARTS: u8 = 0x01;
+// Table flag: sorted end points only store the bytes that differ from the previous one
+pub const FLAG_PREFIX_COMPRESSED: u8 = 0x02;
+// Table flag: chains end at distinguished points instead of after a fixed number of links
+pub const FLAG_DISTINGUISHED: u8 = 0x04;
+// Every flag this version of the code understands
+const KNOWN_FLAGS: u8 = FLAG_INDEXED_STARTS | FLAG_PREFIX_COMPRESSED | FLAG_DISTINGUISHED;
+
+// Magic bytes at the start of every rainbow table
+pub const TABLE_MAGIC: &[u8; 12] = b"rainbowtable";
+
+// One chain as (index, start, end), the index only matters for indexed tables
+pub type ChainRecord = (u64, String, String);
+
+// Metadata stored at the start of every rainbow table
+#[derive(Debug, Clone)]
+pub struct TableHeader {
+    pub version: u8,
+    pub algorithm: String,
+    pub min_len: usize,
+    pub max_len: usize,
+    pub charset_size: u128,
+    pub num_links: usize,
+    pub ascii_offset: u8,
+    pub flags: u8,
+    // Only meaningful when FLAG_INDEXED_STARTS is set
+    pub table_seed: u64,
+    pub index_bytes: u8,
+    // Width of a packed password, only used from v4 on
+    pub point_bytes: u8,
+    // Leading zero bits that make a hash distinguished, only used with FLAG_DISTINGUISHED
+    pub dp_bits: u8,
+    // Size and CRC32 of the chain records, only used from v5 on (see `seal`)
+    pub body_len: u64,
+    pub body_crc: u32,
+}
+
+impl TableHeader {
+    // Parses the header and returns it together with the offset of the first chain
+    pub fn parse(data: &[u8]) -> Result<(TableHeader, usize), Box<dyn std::error::Error>> {
+        // Verify the magic header
+        if data.len() < TABLE_MAGIC.len() + 2 || &data[..TABLE_MAGIC.len()] != TABLE_MAGIC {
+            return Err("Invalid rainbow table format".into());
+        }
+
+        let mut pos = TABLE_MAGIC.len();
+        let version = data[pos];
+        pos += 1;
+        if version == 0 || version > TABLE_VERSION {
+            return Err(format!("Unsupported rainbow table version: {}", version).into());
+        }
+        let algo_len = data[pos] as usize;
+        pos += 1;
+
+        // v1 has a single length byte, v2 stores min and max
+        let len_bytes = if version == 1 { 1 } else { 2 };
+        if data.len() < pos + algo_len + len_bytes + 16 + 16 + 1 {
+            return Err("Rainbow table header is truncated".into());
+        }
+        let algorithm = String::from_utf8(data[pos..pos + algo_len].to_vec())?;
+        pos += algo_len;
+        let min_len = data[pos] as usize;
+        let max_len = data[pos + len_bytes - 1] as usize;
+        pos += len_bytes;
+        let charset_size = u128::from_be_bytes(data[pos..pos + 16].try_into()?);
+        pos += 16;
+        let num_links = u128::from_be_bytes(data[pos..pos + 16].try_into()?) as usize;
+        pos += 16;
+        let ascii_offset = data[pos];
+        pos += 1;
+
+        if min_len == 0 || min_len > max_len {
+            return Err(format!("Invalid password length range {}..={}", min_len, max_len).into());
+        }
+        // The charset has to fit in single bytes, anything else would be built in memory later
+        keyspace::check_charset(ascii_offset, charset_size)?;
+
+        // v3 adds flags, followed by the table seed when starts are index-derived
+        let mut flags = 0;
+        let mut table_seed = 0;
+        let mut index_bytes = 0;
+        if version >= 3 {
+            flags = *data.get(pos).ok_or("Rainbow table header is truncated")?;
+            pos += 1;
+            if flags & !KNOWN_FLAGS != 0 {
+                return Err(format!("Unsupported rainbow table flags: {:#04x}", flags).into());
+            }
+            if flags & FLAG_INDEXED_STARTS != 0 {
+                let seed_bytes = data
+                    .get(pos..pos + 8)
+                    .ok_or("Rainbow table header is truncated")?;
+                table_seed = u64::from_be_bytes(seed_bytes.try_into()?);
+                pos += 8;
+                index_bytes = *data.get(pos).ok_or("Rainbow table header is truncated")?;
+                pos += 1;
+                if index_bytes == 0 || index_bytes > 8 {
+                    return Err(format!("Invalid chain index width: {}", index_bytes).into());
+                }
+            }
+        }
+
+        // v4 stores the width of a packed password
+        let mut point_bytes = 0;
+        if version >= 4 {
+            point_bytes = *data.get(pos).ok_or("Rainbow table header is truncated")?;
+            pos += 1;
+            let expected = keyspace::point_bytes(min_len, max_len, charset_size)?;
+            if point_bytes as usize != expected {
+                return Err(format!(
+                    "Invalid packed password width: {} (expected {})",
+                    point_bytes, expected
+                )
+                .into());
+            }
+        }
+
+        // Distinguished point tables record how many leading zero bits end a chain
+        let mut dp_bits = 0;
+        if flags & FLAG_DISTINGUISHED != 0 {
+            if version < 4 {
+                return Err("Distinguished point tables need format v4".into());
+            }
+            dp_bits = *data.get(pos).ok_or("Rainbow table header is truncated")?;
+            pos += 1;
+        }
+
+        // v5 closes the header with the body length, the body checksum and its own checksum
+        let mut body_len = 0;
+        let mut body_crc = 0;
+        if version >= 5 {
+            let trailer = data
+                .get(pos..pos + 16)
+                .ok_or("Rainbow table header is truncated")?;
+            body_len = u64::from_be_bytes(trailer[..8].try_into()?);
+            body_crc = u32::from_be_bytes(trailer[8..12].try_into()?);
+            let header_crc = u32::from_be_bytes(trailer[12..16].try_into()?);
+            if crc32fast::hash(&data[..pos + 12]) != header_crc {
+                return Err("Rainbow table header checksum mismatch".into());
+            }
+            pos += 16;
+        }
+
+        Ok((
+            TableHeader {
+                version,
+                algorithm,
+                min_len,
+                max_len,
+                charset_size,
+                num_links,
+                ascii_offset,
+                flags,
+                table_seed,
+                index_bytes,
+                point_bytes,
+                dp_bits,
+                body_len,
+                body_crc,
+            },
+            pos,
+        ))
+    }
+
+    // Serializes the header in the layout of `self.version`
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        out.extend_from_slice(TABLE_MAGIC);
+        out.push(self.version);
+        out.push(self.algorithm.len() as u8);
+        out.extend_from_slice(self.algorithm.as_bytes());
+        out.push(self.min_len as u8);
+        if self.version >= 2 {
+            out.push(self.max_len as u8);
+        }
+        out.extend_from_slice(&self.charset_size.to_be_bytes());
+        out.extend_from_slice(&(self.num_links as u128).to_be_bytes());
+        out.push(self.ascii_offset);
+        if self.version >= 3 {
+            out.push(self.flags);
+            if self.indexed_starts() {
+                out.extend_from_slice(&self.table_seed.to_be_bytes());
+                out.push(self.index_bytes);
+            }
+        }
+        if self.version >= 4 {
+            out.push(self.point_bytes);
+            if self.distinguished() {
+                out.push(self.dp_bits);
+            }
+        }
+        if self.version >= 5 {
+            out.extend_from_slice(&self.body_len.to_be_bytes());
+            out.extend_from_slice(&self.body_crc.to_be_bytes());
+            let header_crc = crc32fast::hash(&out);
+            out.extend_from_slice(&header_crc.to_be_bytes());
+        }
+        out
+    }
+
+    // Records the length and checksum of the encoded chains in the header
+    pub fn seal(&mut self, body: &[u8]) {
+        self.body_len = body.len() as u64;
+        self.body_crc = crc32fast::hash(body);
+    }
+
+    // Checks the chain records after the header against the body length and checksum
+    // Tables older than v5 carry no checksum and always pass
+    pub fn verify_body(&self, data: &[u8], pos: usize) -> Result<(), Box<dyn std::error::Error>> {
+        if self.version < 5 {
+            return Ok(());
+        }
+        let body = &data[pos.min(data.len())..];
+        if body.len() as u64 != self.body_len {
+            return Err(format!(
+                "Rainbow table body is {} bytes, header says {}",
+                body.len(),
+                self.body_len
+            )
+            .into());
+        }
+        if crc32fast::hash(body) != self.body_crc {
+            return Err("Rainbow table body checksum mismatch".into());
+        }
+        Ok(())
+    }
+
+    // True when chains end at distinguished points
+    pub fn distinguished(&self) -> bool {
+        self.flags & FLAG_DISTINGUISHED != 0
+    }
+
+    // Serializes chains as (index, start, end) records in the layout of `self.version`
+    // v4 tables are sorted by packed end point, so the chains are sorted in place
+    pub fn encode_chains(
+        &self,
+        chains: &mut [ChainRecord],
+    ) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+        let mut out = Vec::new();
+        if self.version < 4 {
+            for (index, start, end) in chains.iter() {
+                if self.version == 1 {
+                    // v1 chains are fixed-width start/end pairs
+                    if start.len() != self.min_len || end.len() != self.min_len {
+                        return Err("v1 tables only hold fixed-length passwords".into());
+                    }
+                    out.extend_from_slice(start.as_bytes());
+                } else if self.indexed_starts() {
+                    out.extend_from_slice(&index.to_be_bytes()[8 - self.index_bytes as usize..]);
+                } else {
+                    out.push(start.len() as u8);
+                    out.extend_from_slice(start.as_bytes());
+                }
+                if self.version >= 2 {
+                    out.push(end.len() as u8);
+                }
+                out.extend_from_slice(end.as_bytes());
+            }
+            return Ok(out);
+        }
+
+        // Pack every end point first so the chains can be sorted by it
+        let width = self.point_bytes as usize;
+        let mut packed = Vec::with_capacity(chains.len());
+        for (index, start, end) in chains.iter() {
+            let end_bytes = self.encode_point(end)?;
+            let start_bytes = if self.indexed_starts() {
+                index.to_be_bytes()[8 - self.index_bytes as usize..].to_vec()
+            } else {
+                self.encode_point(start)?
+            };
+            packed.push((end_bytes, start_bytes));
+        }
+        let mut order: Vec<usize> = (0..chains.len()).collect();
+        order.sort_by(|a, b| packed[*a].0.cmp(&packed[*b].0));
+
+        let mut prev: Vec<u8> = Vec::new();
+        for i in &order {
+            let (end_bytes, start_bytes) = &packed[*i];
+            out.extend_from_slice(start_bytes);
+            if self.flags & FLAG_PREFIX_COMPRESSED != 0 {
+                // Store how many leading bytes match the previous end point, then the rest
+                let shared = prev
+                    .iter()
+                    .zip(end_bytes.iter())
+                    .take_while(|(a, b)| a == b)
+                    .count()
+                    .min(width);
+                out.push(shared as u8);
+                out.extend_from_slice(&end_bytes[shared..]);
+                prev.clone_from(end_bytes);
+            } else {
+                out.extend_from_slice(end_bytes);
+            }
+        }
+
+        let sorted: Vec<_> = order.iter().map(|i| chains[*i].clone()).collect();
+        chains.clone_from_slice(&sorted);
+        Ok(out)
+    }
+
+    // Packs a password as its keyspace index
+    fn encode_point(&self, pwd: &str) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+        encode_password(
+            pwd,
+            self.min_len,
+            self.max_len,
+            self.ascii_offset,
+            self.charset_size,
+            self.point_bytes as usize,
+        )
+    }
+
+    // Unpacks a password from its keyspace index
+    fn decode_point(&self, bytes: &[u8]) -> Result<String, Box<dyn std::error::Error>> {
+        decode_password(
+            bytes,
+            self.min_len,
+            self.max_len,
+            self.ascii_offset,
+            self.charset_size,
+        )
+    }
+
+    // True when chain starts are derived from an index instead of being stored
+    pub fn indexed_starts(&self) -> bool {
+        self.flags & FLAG_INDEXED_STARTS != 0
+    }
+
+    // The characters passwords in this table are built from
+    // Stops at the last byte value, `parse` refuses charsets that run past it
+    pub fn charset(&self) -> Vec<u8> {
+        (0..self.charset_size)
+            .map_while(|i| u8::try_from(self.ascii_offset as u128 + i).ok())
+            .collect()
+    }
+
+    // Reads every (start, end) chain stored after the header
+    pub fn read_chains(
+        &self,
+        data: &[u8],
+        pos: usize,
+    ) -> Result<Vec<(String, String)>, Box<dyn std::error::Error>> {
+        Ok(self
+            .read_records(data, pos)?
+            .into_iter()
+            .map(|(_, start, end)| (start, end))
+            .collect())
+    }
+
+    // Reads every chain as an (index, start, end) record
+    // The index is the stored chain index for indexed tables and the record number otherwise
+    pub fn read_records(
+        &self,
+        data: &[u8],
+        pos: usize,
+    ) -> Result<Vec<ChainRecord>, Box<dyn std::error::Error>> {
+        let mut chains = Vec::new();
+        self.scan_records(data, pos, |record| {
+            chains.push(record.decode()?);
+            Ok(true)
+        })?;
+        Ok(chains)
+    }
+
+    // Counts the chains without decoding their passwords
+    pub fn count_chains(
+        &self,
+        data: &[u8],
+        pos: usize,
+    ) -> Result<usize, Box<dyn std::error::Error>> {
+        let mut count = 0;
+        self.scan_records(data, pos, |_| {
+            count += 1;
+            Ok(true)
+        })?;
+        Ok(count)
+    }
+
+    // Walks the chain records one at a time as they are stored, stopping early when `visit`
+    // returns false, so callers only decode and keep the records they need
+    pub fn scan_records(
+        &self,
+        data: &[u8],
+        mut pos: usize,
+        mut visit: impl FnMut(&RawRecord<'_>) -> Result<bool, Box<dyn std::error::Error>>,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        // From v5 on the body length is known, anything after it is not a chain
+        let data = if self.version >= 5 {
+            &data[..(pos as u64 + self.body_len).min(data.len() as u64) as usize]
+        } else {
+            data
+        };
+        let allowed = self.charset();
+        let mut number = 0u64;
+        let mut record = |index: Option<u64>, start: Point<'_>, end: Point<'_>| {
+            let raw = RawRecord {
+                header: self,
+                allowed: &allowed,
+                index: index.unwrap_or(number),
+                start,
+                end,
+            };
+            number += 1;
+            visit(&raw)
+        };
+
+        if self.version == 1 {
+            // v1 chains are fixed-width start/end pairs
+            let chain_len = self.min_len * 2;
+            while pos + chain_len <= data.len() {
+                let start = Point::Text(&data[pos..pos + self.min_len]);
+                let end = Point::Text(&data[pos + self.min_len..pos + chain_len]);
+                pos += chain_len;
+                if !record(None, start, end)? {
+                    break;
+                }
+            }
+            return Ok(());
+        }
+
+        if self.version >= 4 {
+            // v4 chains are packed keyspace indices, possibly prefix compressed
+            let width = self.point_bytes as usize;
+            let mut prev = vec![0u8; width];
+            while pos < data.len() {
+                let (index, start) = if self.indexed_starts() {
+                    let index = read_index(data, &mut pos, self.index_bytes as usize)?;
+                    (Some(index), Point::Indexed)
+                } else {
+                    let bytes = data.get(pos..pos + width).ok_or("Truncated chain record")?;
+                    pos += width;
+                    (None, Point::Packed(bytes))
+                };
+                if self.flags & FLAG_PREFIX_COMPRESSED != 0 {
+                    let shared = *data.get(pos).ok_or("Truncated chain record")? as usize;
+                    pos += 1;
+                    if shared > width {
+                        return Err("Corrupt prefix length in chain record".into());
+                    }
+                    let rest = data
+                        .get(pos..pos + width - shared)
+                        .ok_or("Truncated chain record")?;
+                    prev[shared..].copy_from_slice(rest);
+                    pos += width - shared;
+                } else {
+                    let bytes = data.get(pos..pos + width).ok_or("Truncated chain record")?;
+                    prev.copy_from_slice(bytes);
+                    pos += width;
+                }
+                if !record(index, start, Point::Packed(&prev))? {
+                    break;
+                }
+            }
+            return Ok(());
+        }
+
+        // v2 and v3 chains store length-prefixed passwords, or the chain index for the start
+        while pos < data.len() {
+            let (index, start) = if self.indexed_starts() {
+                let index = read_index(data, &mut pos, self.index_bytes as usize)?;
+                (Some(index), Point::Indexed)
+            } else {
+                (None, Point::Text(read_len_prefixed(data, &mut pos)?))
+            };
+            let end = Point::Text(read_len_prefixed(data, &mut pos)?);
+            if !record(index, start, end)? {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    // True when chains from `other` can be stored in a table with this header
+    pub fn compatible_with(&self, other: &TableHeader) -> bool {
+        self.algorithm.eq_ignore_ascii_case(&other.algorithm)
+            && self.min_len == other.min_len
+            && self.max_len == other.max_len
+            && self.charset_size == other.charset_size
+            && self.ascii_offset == other.ascii_offset
+            && self.num_links == other.num_links
+            && self.distinguished() == other.distinguished()
+            && self.dp_bits == other.dp_bits
+    }
+}
+
+// A whole rainbow table held in memory, used to read, rewrite, merge and split tables
+#[derive(Debug, Clone)]
+pub struct RainbowTable {
+    pub header: TableHeader,
+    pub chains: Vec<ChainRecord>,
+}
+
+impl RainbowTable {
+    // Parses a table from its bytes, checking the checksums when the format has them
+    pub fn from_bytes(data: &[u8]) -> Result<RainbowTable, Box<dyn std::error::Error>> {
+        let (header, pos) = TableHeader::parse(data)?;
+        header.verify_body(data, pos)?;
+        let chains = header.read_records(data, pos)?;
+        Ok(RainbowTable { header, chains })
+    }
+
+    // Reads a table from a file
+    pub fn read(path: &str) -> Result<RainbowTable, Box<dyn std::error::Error>> {
+        RainbowTable::from_bytes(&std::fs::read(path)?)
+    }
+
+    // Serializes the table in the layout of `header.version`
+    // Sorts the chains for v4 and later and refreshes the body checksum
+    pub fn to_bytes(&mut self) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+        if self.header.version >= 4 {
+            self.header.point_bytes = keyspace::point_bytes(
+                self.header.min_len,
+                self.header.max_len,
+                self.header.charset_size,
+            )? as u8;
+        }
+        if self.header.indexed_starts() {
+            let largest = self.chains.iter().map(|c| c.0).max().unwrap_or(0);
+            self.header.index_bytes = ((64 - largest.leading_zeros()).div_ceil(8)).max(1) as u8;
+        }
+        let body = self.header.encode_chains(&mut self.chains)?;
+        self.header.seal(&body);
+        let mut out = self.header.to_bytes();
+        out.extend_from_slice(&body);
+        Ok(out)
+    }
+
+    // Writes the table to a file, going through a temporary file so readers never see half of it
+    pub fn write(&mut self, path: &str) -> Result<(), Box<dyn std::error::Error>> {
+        let bytes = self.to_bytes()?;
+        let tmp = format!("{}.tmp", path);
+        std::fs::write(&tmp, &bytes)?;
+        std::fs::rename(&tmp, path)?;
+        Ok(())
+    }
+}
+
+// A password as stored in a chain record
+#[derive(Debug, Clone, Copy)]
+enum Point<'a> {
+    // Plain password bytes, v1 to v3
+    Text(&'a [u8]),
+    // A keyspace index, v4 on
+    Packed(&'a [u8]),
+    // Derived from the chain index and the table seed
+    Indexed,
+}
+
+// One chain record before its passwords are decoded, see `TableHeader::scan_records`
+pub struct RawRecord<'a> {
+    header: &'a TableHeader,
+    allowed: &'a [u8],
+    pub index: u64,
+    start: Point<'a>,
+    end: Point<'a>,
+}
+
+impl RawRecord<'_> {
+    // The stored bytes of the end point, equal ends have equal bytes
+    pub fn end_bytes(&self) -> &[u8] {
+        match self.end {
+            Point::Text(bytes) | Point::Packed(bytes) => bytes,
+            Point::Indexed => &[],
+        }
+    }
+
+    // Decodes the record into its (index, start, end) passwords
+    pub fn decode(&self) -> Result<ChainRecord, Box<dyn std::error::Error>> {
+        Ok((
+            self.index,
+            self.decode_point(self.start)?,
+            self.decode_point(self.end)?,
+        ))
+    }
+
+    fn decode_point(&self, point: Point<'_>) -> Result<String, Box<dyn std::error::Error>> {
+        let header = self.header;
+        match point {
+            Point::Text(bytes) => Ok(String::from_utf8_lossy(bytes).to_string()),
+            Point::Packed(bytes) => header.decode_point(bytes),
+            Point::Indexed => Ok(index_start(
+                header.table_seed,
+                self.index,
+                header.min_len,
+                header.max_len,
+                self.allowed,
+            )),
+        }
+    }
+}
+
+// Reads a big-endian chain index of `width` bytes
+fn read_index(
+    data: &[u8],
+    pos: &mut usize,
+    width: usize,
+) -> Result<u64, Box<dyn std::error::Error>> {
+    let bytes = data
+        .get(*pos..*pos + width)
+        .ok_or("Truncated chain record")?;
+    *pos += width;
+    Ok(bytes.iter().fold(0u64, |acc, b| (acc << 8) | *b as u64))
+}
+
+// Reads one length-prefixed password from a v2 chain record
+fn read_len_prefixed<'a>(
+    data: &'a [u8],
+    pos: &mut usize,
+) -> Result<&'a [u8], Box<dyn std::error::Error>> {
+    let len = *data.get(*pos).ok_or("Truncated chain record")? as usize;
+    *pos += 1;
+    let bytes = data.get(*pos..*pos + len).ok_or("Truncated chain record")?;
+    *pos += len;
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header(version: u8, ascii_offset: u8, charset_size: u128, point_bytes: u8) -> TableHeader {
+        TableHeader {
+            version,
+            algorithm: "md5".to_string(),
+            min_len: 1,
+            max_len: 1,
+            charset_size,
+            num_links: 10,
+            ascii_offset,
+            flags: 0,
+            table_seed: 0,
+            index_bytes: 0,
+            point_bytes,
+            dp_bits: 0,
+            body_len: 0,
+            body_crc: 0,
+        }
+    }
+
+    #[test]
+    fn header_with_oversized_charset_is_rejected() {
+        // A charset this large would be collected into memory by `charset()`
+        let huge = header(4, 0, 1 << 40, 5).to_bytes();
+        assert!(TableHeader::parse(&huge).is_err());
+
+        for version in 1..=TABLE_VERSION {
+            assert!(TableHeader::parse(&header(version, 0, 0, 1).to_bytes()).is_err());
+            assert!(TableHeader::parse(&header(version, 200, 57, 1).to_bytes()).is_err());
+            assert!(TableHeader::parse(&header(version, 200, 56, 1).to_bytes()).is_ok());
+            assert!(TableHeader::parse(&header(version, 0, 256, 1).to_bytes()).is_ok());
+        }
+    }
+
+    #[test]
+    fn prefix_compressed_records_read_back() -> Result<(), Box<dyn std::error::Error>> {
+        let mut header = header(5, 32, 95, 0);
+        header.min_len = 2;
+        header.max_len = 4;
+        header.flags = FLAG_PREFIX_COMPRESSED;
+        let allowed = header.charset();
+        // Neighbouring end points share most of their leading bytes
+        let mut chains: Vec<ChainRecord> = (0..40u64)
+            .map(|i| {
+                let start = index_start(1, i, 2, 4, &allowed);
+                let end = format!("ab{}", (b'a' + (i % 26) as u8) as char);
+                (i, start, end)
+            })
+            .collect();
+        chains.push((40, "zz".to_string(), "zz".to_string()));
+        let mut table = RainbowTable {
+            header,
+            chains: chains.clone(),
+        };
+        let bytes = table.to_bytes()?;
+        let read = RainbowTable::from_bytes(&bytes)?;
+
+        let pairs = |chains: &[ChainRecord]| {
+            let mut pairs: Vec<_> = chains
+                .iter()
+                .map(|(_, s, e)| (s.clone(), e.clone()))
+                .collect();
+            pairs.sort();
+            pairs
+        };
+        assert_eq!(pairs(&read.chains), pairs(&chains));
+        // Duplicate end points only cost their one-byte prefix count
+        assert!(
+            read.header.body_len < (chains.len() * 2 * read.header.point_bytes as usize) as u64
+        );
+
+        // A prefix count past the point width is corrupt, not a panic
+        let (_, pos) = TableHeader::parse(&bytes)?;
+        let mut corrupt = bytes.clone();
+        corrupt[pos + read.header.point_bytes as usize] = 0xff;
+        assert!(read.header.read_records(&corrupt, pos).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn charset_never_wraps_past_the_last_byte() {
+        assert_eq!(
+            header(4, 250, 100, 1).charset(),
+            (250..=255).collect::<Vec<u8>>()
+        );
+    }
+}
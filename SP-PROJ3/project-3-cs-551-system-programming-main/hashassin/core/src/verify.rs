@@ -0,0 +1,124 @@
+// Integrity checks for rainbow tables: checksums plus recomputing a sample of chains
+
+use crate::{TableHeader, walk_chain};
+use rand::seq::index::sample;
+use tracing::{info, warn};
+
+// Outcome of verify_table
+#[derive(Debug, Clone)]
+pub struct VerifyReport {
+    pub chains: usize,
+    // False for tables older than v5, which carry no checksums
+    pub checksummed: bool,
+    pub sampled: usize,
+    // (start, stored end, recomputed end) of every sampled chain that did not match
+    pub mismatches: Vec<(String, String, Option<String>)>,
+}
+
+impl VerifyReport {
+    pub fn is_ok(&self) -> bool {
+        self.mismatches.is_empty()
+    }
+}
+
+// Checks the header and body checksums, then recomputes `samples` random chains
+// from their start points and compares each end point with the stored one
+pub fn verify_table(
+    in_file: &str,
+    samples: usize,
+) -> Result<VerifyReport, Box<dyn std::error::Error>> {
+    let data = std::fs::read(in_file)?;
+    // A bad header checksum already fails here
+    let (header, pos) = TableHeader::parse(&data)?;
+    header.verify_body(&data, pos)?;
+    let chains = header.read_chains(&data, pos)?;
+
+    let allowed = header.charset();
+    let dp_bits = header.distinguished().then_some(header.dp_bits);
+    let picked = sample(
+        &mut rand::thread_rng(),
+        chains.len(),
+        samples.min(chains.len()),
+    );
+    info!("Recomputing {} of {} chains", picked.len(), chains.len());
+
+    let mut mismatches = Vec::new();
+    for i in picked.iter() {
+        let (start, end) = &chains[i];
+        let recomputed = walk_chain(
+            start,
+            &header.algorithm,
+            header.min_len,
+            header.max_len,
+            &allowed,
+            header.num_links,
+            dp_bits,
+        )
+        .map(|(pwd, _)| pwd);
+        if recomputed.as_deref() != Some(end.as_str()) {
+            warn!("Chain starting at {:?} does not end at {:?}", start, end);
+            mismatches.push((start.clone(), end.clone(), recomputed));
+        }
+    }
+
+    Ok(VerifyReport {
+        chains: chains.len(),
+        checksummed: header.version >= 5,
+        sampled: picked.len(),
+        mismatches,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ChainStarts, Hooks, RainbowTable, TableParams, gen_rainbow_table};
+
+    #[test]
+    fn a_changed_end_point_is_reported() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = std::env::temp_dir().join(format!("hashassin-verify-{}", std::process::id()));
+        std::fs::create_dir_all(&dir)?;
+        let out = dir.join("t.rainbow").to_string_lossy().to_string();
+        let params = TableParams {
+            algorithm: "md5".to_string(),
+            num_links: 10,
+            threads: 1,
+            len_range: Some((4, 4)),
+            compress: false,
+            dp_bits: None,
+            resume: false,
+            checkpoint_every: 1000,
+        };
+        let starts = ChainStarts::Indexed {
+            count: 20,
+            table_seed: 9,
+        };
+        gen_rainbow_table(starts, &out, &params, &Hooks::default())?;
+
+        let report = verify_table(&out, 100)?;
+        assert!(report.is_ok());
+        assert!(report.checksummed);
+        assert_eq!((report.chains, report.sampled), (20, 20));
+
+        // Rewritten through RainbowTable so the checksums still match
+        let mut table = RainbowTable::read(&out)?;
+        let (_, start, end) = &mut table.chains[7];
+        let stored = end.clone();
+        *end = if stored == "~~~~" { "    " } else { "~~~~" }.to_string();
+        let (start, end) = (start.clone(), end.clone());
+        table.write(&out)?;
+
+        let report = verify_table(&out, 100)?;
+        assert_eq!(report.mismatches, vec![(start, end, Some(stored))]);
+
+        // Corrupting the file itself fails the body checksum
+        let mut data = std::fs::read(&out)?;
+        let last = data.len() - 1;
+        data[last] ^= 1;
+        std::fs::write(&out, &data)?;
+        assert!(verify_table(&out, 100).is_err());
+
+        std::fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,133 @@
+// Per-connection authentication state
+//
+// Without tokens every connection may run every command. With tokens a connection starts
+// out unauthenticated and may only ask for a challenge and answer it. Each challenge can be
+// answered once, a wrong answer is refused and the connection closed.
+// Jobs belong to the token that submitted them, only that token and admin tokens see them.
+
+use crate::{Response, message};
+use hashassin_core::{AuthToken, MAC_LEN, Scope, Status, new_challenge, verify_response};
+use std::sync::Arc;
+use tokio::io::AsyncReadExt;
+use tokio::net::TcpStream;
+use tracing::{error, info};
+
+pub(crate) struct Session {
+    tokens: Arc<Vec<AuthToken>>,
+    scope: Option<Scope>,
+    // Index of the token the connection authenticated with
+    token: Option<usize>,
+    challenge: Option<Vec<u8>>,
+}
+
+// Who a job command runs for, the token it was authenticated with, None on an open server
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Caller {
+    token: Option<usize>,
+    admin: bool,
+}
+
+impl Caller {
+    // The owner recorded on the jobs this caller submits
+    pub(crate) fn owner(&self) -> Option<usize> {
+        self.token
+    }
+
+    // Admins see every job, everyone else only their own
+    pub(crate) fn may_see(&self, owner: Option<usize>) -> bool {
+        self.admin || self.token == owner
+    }
+}
+
+impl Session {
+    pub(crate) fn new(tokens: Arc<Vec<AuthToken>>) -> Session {
+        let scope = tokens.is_empty().then_some(Scope::Admin);
+        Session {
+            tokens,
+            scope,
+            token: None,
+            challenge: None,
+        }
+    }
+
+    pub(crate) fn caller(&self) -> Caller {
+        Caller {
+            token: self.token,
+            admin: self.scope == Some(Scope::Admin),
+        }
+    }
+
+    // Refuses a command the connection may not run, before any of its request is read
+    pub(crate) fn check(&self, command: &str) -> Option<Response> {
+        let needed = match command {
+            "challg" | "auth\0\0" => return None,
+            "upload" | "upbegn" | "upchnk" | "upoffs" | "updone" | "delete" => Scope::Admin,
+            _ => Scope::Read,
+        };
+        match self.scope {
+            Some(scope) if scope.allows(needed) => None,
+            Some(scope) => Some(message(
+                Status::Forbidden,
+                &format!(
+                    "{} needs an {} token, this connection has a {} token",
+                    command.trim_end_matches('\0'),
+                    needed,
+                    scope
+                ),
+            )),
+            None => Some(message(
+                Status::Unauthorized,
+                "This server needs a token, authenticate first",
+            )),
+        }
+    }
+
+    // Challenge handler: version, answers with a fresh nonce
+    pub(crate) async fn handle_challenge(
+        &mut self,
+        stream: &mut TcpStream,
+    ) -> std::io::Result<Response> {
+        let mut version = [0u8; 1];
+        stream.read_exact(&mut version).await?;
+        let challenge = new_challenge().to_vec();
+        self.challenge = Some(challenge.clone());
+        Ok((Status::Ok, challenge))
+    }
+
+    // Auth handler: version | HMAC-SHA256(token, nonce), answers with the token's scope
+    pub(crate) async fn handle_auth(
+        &mut self,
+        stream: &mut TcpStream,
+    ) -> std::io::Result<Response> {
+        let mut version = [0u8; 1];
+        stream.read_exact(&mut version).await?;
+        let mut response = [0u8; MAC_LEN];
+        stream.read_exact(&mut response).await?;
+
+        if self.tokens.is_empty() {
+            return Ok(message(Status::Ok, &Scope::Admin.to_string()));
+        }
+        let Some(challenge) = self.challenge.take() else {
+            return Ok(message(
+                Status::Unauthorized,
+                "Ask for a challenge before authenticating",
+            ));
+        };
+        match verify_response(&self.tokens, &challenge, &response) {
+            Some((index, scope)) => {
+                info!(
+                    "Connection authenticated with token {} ({} scope)",
+                    index + 1,
+                    scope
+                );
+                self.scope = Some(scope);
+                self.token = Some(index);
+                Ok(message(Status::Ok, &scope.to_string()))
+            }
+            None => {
+                error!("Connection failed to authenticate");
+                Ok(message(Status::Unauthorized, "Wrong token"))
+            }
+        }
+    }
+}
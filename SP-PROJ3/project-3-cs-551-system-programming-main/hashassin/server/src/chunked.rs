@@ -0,0 +1,398 @@
+// Chunked, resumable table uploads
+//
+// An upload is begun with the table's name, size and file checksum and gets an id. Chunks
+// are appended to a staging file in order, each checked against its own CRC32 and fed to a
+// TableValidator, so a broken table is refused as early as with a single request upload.
+// When the connection drops the client asks for the upload's offset, or begins the same
+// upload again, and carries on from there. Once every byte is in, the done command checks
+// the whole table against the file CRC32 it was begun with and its own checksums, then
+// moves the staging file into the table store. Uploads nobody has touched for
+// UPLOAD_IDLE_TIMEOUT are dropped along with their staged data, and at most
+// MAX_PENDING_UPLOADS uploads may be staged at once.
+
+use crate::store::map_file;
+use crate::validate::TableValidator;
+use crate::{RainbowTables, Response, check_upload, message, name_taken, read_name};
+use hashassin_core::{MAX_CHUNK_SIZE, Status, TableInfo, UPLOAD_FLAG_REPLACE, chunk_crc};
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{Seek, SeekFrom, Write};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::io::AsyncReadExt;
+use tokio::net::TcpStream;
+use tracing::{error, info};
+
+// The upload list is only ever locked briefly and never while waiting for another lock, so
+// handlers may touch it while holding an upload's progress or the table store
+pub(crate) type Uploads = Arc<Mutex<HashMap<u64, Arc<PartialUpload>>>>;
+
+// Ids handed out to chunked uploads
+static NEXT_UPLOAD_ID: AtomicU64 = AtomicU64::new(1);
+
+// How long an upload may go without a request before it is dropped, and how often to look
+const UPLOAD_IDLE_TIMEOUT: Duration = Duration::from_secs(60 * 60);
+const EXPIRE_INTERVAL: Duration = Duration::from_secs(60);
+// How many uploads may be unfinished at once, each one holds a staging file
+const MAX_PENDING_UPLOADS: usize = 16;
+
+// An upload that has not been completed yet
+pub(crate) struct PartialUpload {
+    name: String,
+    replace: bool,
+    size: u64,
+    // CRC32 of the whole table, tells a resumed upload apart from a new one of the same name
+    file_crc: u32,
+    path: PathBuf,
+    progress: Mutex<UploadProgress>,
+}
+
+struct UploadProgress {
+    received: u64,
+    validator: TableValidator,
+    file: File,
+    // When a request last used the upload
+    touched: Instant,
+}
+
+impl PartialUpload {
+    // Drops the staged data of an upload that can never complete
+    fn discard(&self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+// Begin handler: version | flags | name_len | name | size u64 | file crc32
+// Answers with the upload id and the offset to continue from, 0 unless it is resumed
+pub(crate) async fn handle_begin(
+    stream: &mut TcpStream,
+    tables: &RainbowTables,
+    uploads: &Uploads,
+    max_size: u64,
+) -> std::io::Result<Response> {
+    let mut header = [0u8; 2];
+    stream.read_exact(&mut header).await?;
+    if header[0] != 1 {
+        return Ok(message(
+            Status::BadRequest,
+            &format!("Unsupported upload request version: {}", header[0]),
+        ));
+    }
+    let replace = header[1] & UPLOAD_FLAG_REPLACE != 0;
+    let name = read_name(stream).await?;
+    let size = stream.read_u64().await?;
+    let file_crc = stream.read_u32().await?;
+
+    if let Some(refusal) = check_upload(tables, &name, size, replace, max_size) {
+        return Ok(refusal);
+    }
+
+    let resumed = uploads.lock().ok().and_then(|map| {
+        map.iter()
+            .find(|(_, upload)| {
+                upload.name == name
+                    && upload.size == size
+                    && upload.file_crc == file_crc
+                    && upload.replace == replace
+            })
+            .map(|(id, upload)| (*id, Arc::clone(upload)))
+    });
+    if let Some((id, upload)) = resumed {
+        let Ok(mut progress) = upload.progress.lock() else {
+            return Ok(message(Status::InternalError, "Upload is unavailable"));
+        };
+        progress.touched = Instant::now();
+        info!(
+            "Resuming upload {} of '{}' at {} of {} bytes",
+            id, name, progress.received, size
+        );
+        return Ok(offsets(&[id, progress.received]));
+    }
+
+    let Ok(staging) = tables.lock().map(|store| store.staging_dir()) else {
+        return Ok(message(Status::InternalError, "Table store is unavailable"));
+    };
+    let id = NEXT_UPLOAD_ID.fetch_add(1, Ordering::Relaxed);
+    let path = staging.join(format!("{}.part", id));
+    let file = File::create(&path)?;
+    let upload = PartialUpload {
+        name: name.clone(),
+        replace,
+        size,
+        file_crc,
+        path,
+        progress: Mutex::new(UploadProgress {
+            received: 0,
+            validator: TableValidator::new(size),
+            file,
+            touched: Instant::now(),
+        }),
+    };
+    let Ok(mut map) = uploads.lock() else {
+        upload.discard();
+        return Ok(message(Status::InternalError, "Upload list is unavailable"));
+    };
+    if map.len() >= MAX_PENDING_UPLOADS {
+        upload.discard();
+        error!(
+            "Refused upload of '{}', {} uploads are pending",
+            name,
+            map.len()
+        );
+        return Ok(message(
+            Status::TooLarge,
+            &format!(
+                "{} uploads are unfinished, the limit is {}, try again later",
+                map.len(),
+                MAX_PENDING_UPLOADS
+            ),
+        ));
+    }
+    map.insert(id, Arc::new(upload));
+    info!("Began upload {} of '{}' ({} bytes)", id, name, size);
+    Ok(offsets(&[id, 0]))
+}
+
+// Chunk handler: version | id u64 | offset u64 | len u32 | crc32 | data
+// Answers with the offset the next chunk starts at
+pub(crate) async fn handle_chunk(
+    stream: &mut TcpStream,
+    uploads: &Uploads,
+) -> std::io::Result<Response> {
+    let mut version = [0u8; 1];
+    stream.read_exact(&mut version).await?;
+    let id = stream.read_u64().await?;
+    let offset = stream.read_u64().await?;
+    let len = stream.read_u32().await?;
+    let crc = stream.read_u32().await?;
+    if len > MAX_CHUNK_SIZE {
+        return Ok(message(
+            Status::TooLarge,
+            &format!("Chunk is {} bytes, the limit is {}", len, MAX_CHUNK_SIZE),
+        ));
+    }
+    let mut data = vec![0u8; len as usize];
+    stream.read_exact(&mut data).await?;
+
+    let Some(end) = offset.checked_add(len as u64) else {
+        return Ok(message(
+            Status::BadRequest,
+            &format!("Chunk offset {} is out of range", offset),
+        ));
+    };
+
+    let Some(upload) = find(uploads, id) else {
+        return Ok(no_such_upload(id));
+    };
+    if chunk_crc(&data) != crc {
+        error!("Chunk at {} of upload {} failed its checksum", offset, id);
+        return Ok(mismatch(&upload, "Chunk failed its checksum"));
+    }
+
+    // Writing to the staging file blocks, so it stays off the async workers
+    let uploads = Arc::clone(uploads);
+    tokio::task::spawn_blocking(move || {
+        let Ok(mut progress) = upload.progress.lock() else {
+            return Ok(message(Status::InternalError, "Upload is unavailable"));
+        };
+        progress.touched = Instant::now();
+        // A resent chunk whose answer got lost is already in
+        if end <= progress.received {
+            return Ok(offsets(&[progress.received]));
+        }
+        if offset != progress.received {
+            return Ok(mismatch_at(
+                progress.received,
+                "Chunk is not at the upload's offset",
+            ));
+        }
+        // A write that failed part way may have left bytes behind, drop them before the
+        // resent chunk goes in. The validator only sees chunks once they are written
+        let received = progress.received;
+        if progress.file.stream_position()? != received {
+            progress.file.set_len(received)?;
+            progress.file.seek(SeekFrom::Start(received))?;
+        }
+        progress.file.write_all(&data)?;
+        if let Err(e) = progress.validator.feed(&data) {
+            error!("Rejected upload {} of '{}': {}", id, upload.name, e);
+            upload.discard();
+            forget(&uploads, id);
+            return Ok(message(
+                Status::BadRequest,
+                &format!("Rejected rainbow table '{}': {}", upload.name, e),
+            ));
+        }
+        progress.received += len as u64;
+        Ok(offsets(&[progress.received]))
+    })
+    .await?
+}
+
+// Offset handler: version | id u64, answers with the offset to resume from
+pub(crate) async fn handle_offset(
+    stream: &mut TcpStream,
+    uploads: &Uploads,
+) -> std::io::Result<Response> {
+    let mut version = [0u8; 1];
+    stream.read_exact(&mut version).await?;
+    let id = stream.read_u64().await?;
+
+    let Some(upload) = find(uploads, id) else {
+        return Ok(no_such_upload(id));
+    };
+    let Ok(mut progress) = upload.progress.lock() else {
+        return Ok(message(Status::InternalError, "Upload is unavailable"));
+    };
+    progress.touched = Instant::now();
+    Ok(offsets(&[progress.received]))
+}
+
+// Done handler: version | id u64
+// Checks the complete table and stores it under the upload's name
+pub(crate) async fn handle_done(
+    stream: &mut TcpStream,
+    tables: &RainbowTables,
+    uploads: &Uploads,
+) -> std::io::Result<Response> {
+    let mut version = [0u8; 1];
+    stream.read_exact(&mut version).await?;
+    let id = stream.read_u64().await?;
+
+    let Some(upload) = find(uploads, id) else {
+        return Ok(no_such_upload(id));
+    };
+    let tables = Arc::clone(tables);
+    let uploads = Arc::clone(uploads);
+    tokio::task::spawn_blocking(move || {
+        let Ok(progress) = upload.progress.lock() else {
+            return Ok(message(Status::InternalError, "Upload is unavailable"));
+        };
+        if let Err(e) = progress.validator.finish() {
+            return Ok(message(
+                Status::BadRequest,
+                &format!("Upload {} is incomplete: {}", id, e),
+            ));
+        }
+        progress.file.sync_all()?;
+
+        // Refuse tables that are not the file the client began with, or whose header or body
+        // checksum does not match. Tables before v5 have no checksums of their own
+        let checked = map_file(&upload.path)
+            .map_err(|e| e.to_string())
+            .and_then(|map| {
+                if chunk_crc(&map) != upload.file_crc {
+                    return Err("Table does not match the checksum it was begun with".to_string());
+                }
+                TableInfo::from_table(&upload.name, &map).map_err(|e| e.to_string())
+            });
+        let info = match checked {
+            Ok(info) => info,
+            Err(e) => {
+                error!("Rejected rainbow table '{}': {}", upload.name, e);
+                upload.discard();
+                forget(&uploads, id);
+                return Ok(message(
+                    Status::BadRequest,
+                    &format!("Rejected rainbow table '{}': {}", upload.name, e),
+                ));
+            }
+        };
+
+        // A store without a data directory reads the table in before it is locked
+        let Ok(stager) = tables.lock().map(|store| store.stager()) else {
+            return Ok(message(Status::InternalError, "Table store is unavailable"));
+        };
+        let staged = stager.file(&upload.path)?;
+        let Ok(mut store) = tables.lock() else {
+            staged.discard();
+            return Ok(message(Status::InternalError, "Table store is unavailable"));
+        };
+        forget(&uploads, id);
+        if store.contains(&upload.name) && !upload.replace {
+            staged.discard();
+            return Ok(name_taken(&upload.name));
+        }
+        store.insert(info, staged)?;
+        info!(
+            "Received rainbow table '{}' ({} bytes) in upload {}",
+            upload.name, upload.size, id
+        );
+        Ok(message(
+            Status::Ok,
+            &format!("Stored rainbow table '{}'", upload.name),
+        ))
+    })
+    .await?
+}
+
+// Drops uploads that have been idle for UPLOAD_IDLE_TIMEOUT, runs for as long as the server
+pub(crate) async fn expire_idle(uploads: Uploads) {
+    let mut ticks = tokio::time::interval(EXPIRE_INTERVAL);
+    loop {
+        ticks.tick().await;
+        // The list lock is let go before any upload is locked, see `Uploads`
+        let all: Vec<(u64, Arc<PartialUpload>)> = match uploads.lock() {
+            Ok(map) => map
+                .iter()
+                .map(|(id, upload)| (*id, Arc::clone(upload)))
+                .collect(),
+            Err(_) => continue,
+        };
+        for (id, upload) in all {
+            // An upload that is locked is busy with a request right now
+            let Ok(progress) = upload.progress.try_lock() else {
+                continue;
+            };
+            if progress.touched.elapsed() < UPLOAD_IDLE_TIMEOUT {
+                continue;
+            }
+            info!(
+                "Dropping upload {} of '{}', idle at {} of {} bytes",
+                id, upload.name, progress.received, upload.size
+            );
+            forget(&uploads, id);
+            upload.discard();
+        }
+    }
+}
+
+fn find(uploads: &Uploads, id: u64) -> Option<Arc<PartialUpload>> {
+    uploads.lock().ok()?.get(&id).map(Arc::clone)
+}
+
+fn forget(uploads: &Uploads, id: u64) {
+    if let Ok(mut map) = uploads.lock() {
+        map.remove(&id);
+    }
+}
+
+// An OK response carrying big-endian u64s
+fn offsets(values: &[u64]) -> Response {
+    let payload = values
+        .iter()
+        .flat_map(|value| value.to_be_bytes())
+        .collect();
+    (Status::Ok, payload)
+}
+
+// A ChunkMismatch response, its message ends with the offset to resend from
+fn mismatch(upload: &PartialUpload, reason: &str) -> Response {
+    match upload.progress.lock() {
+        Ok(progress) => mismatch_at(progress.received, reason),
+        Err(_) => message(Status::InternalError, "Upload is unavailable"),
+    }
+}
+
+fn mismatch_at(received: u64, reason: &str) -> Response {
+    message(
+        Status::ChunkMismatch,
+        &format!("{}, resend from offset {}", reason, received),
+    )
+}
+
+fn no_such_upload(id: u64) -> Response {
+    message(Status::NotFound, &format!("No upload with id {}", id))
+}
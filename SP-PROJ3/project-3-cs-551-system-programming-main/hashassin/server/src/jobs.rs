@@ -0,0 +1,516 @@
+// Crack jobs and the registry the status, result and cancel commands read
+//
+// Every crack request becomes a job. The job runs in its own task on the compute pool, so
+// it carries on when the connection that submitted it goes away. Plain crack requests wait
+// for the job on the same connection, submitted jobs are polled with status and result.
+// Finished jobs keep their output until FINISHED_JOBS_KEPT newer jobs have finished.
+// A job belongs to the token that submitted it, other read tokens are told it does not exist.
+// A job can also send every result down a channel as it is found, for streamed cracks.
+
+use crate::auth::Caller;
+use crate::pool::ComputePool;
+use crate::store::TableData;
+use crate::{Response, SharedCache, message};
+use hashassin_core::{CancelToken, CrackSink, HashFile, Hooks, Progress, Status, crack_table};
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use tokio::io::AsyncReadExt;
+use tokio::net::TcpStream;
+use tokio::sync::mpsc::UnboundedSender;
+use tokio::task::JoinHandle;
+use tracing::{error, info};
+
+pub(crate) type Jobs = Arc<std::sync::Mutex<HashMap<u64, Job>>>;
+// A hash and its password, None once every table has missed it
+pub(crate) type CrackResult = (String, Option<String>);
+// The task running a job, it ends with the job's final state and result
+pub(crate) type JobTask = JoinHandle<(&'static str, Response)>;
+
+// How many finished jobs are remembered
+const FINISHED_JOBS_KEPT: usize = 256;
+// How many jobs may be queued or running at once, each one holds its hash file
+const MAX_PENDING_JOBS: usize = 32;
+
+// Ids handed out to crack jobs
+static NEXT_JOB_ID: AtomicU64 = AtomicU64::new(1);
+
+// What the status command reports about a job
+pub(crate) struct Job {
+    // The token that submitted the job, None on an open server
+    owner: Option<usize>,
+    state: &'static str,
+    // Progress through the current table
+    done: u64,
+    total: u64,
+    rate: f64,
+    tables_done: usize,
+    tables_total: usize,
+    // hash, password pairs found so far, in the order they were found
+    cracked: Vec<(String, String)>,
+    cancel: CancelToken,
+    // The response to the result command, once the job has finished
+    result: Option<Response>,
+}
+
+impl Job {
+    fn new(owner: Option<usize>, tables_total: usize, cancel: CancelToken) -> Job {
+        Job {
+            owner,
+            state: "queued",
+            done: 0,
+            total: 0,
+            rate: 0.0,
+            tables_done: 0,
+            tables_total,
+            cracked: Vec::new(),
+            cancel,
+            result: None,
+        }
+    }
+
+    fn finished(&self) -> bool {
+        self.result.is_some()
+    }
+
+    fn status_line(&self, id: u64) -> String {
+        format!(
+            "{}\t{}\t{}/{}\t{:.0}/s\t{}/{} tables\t{} cracked\n",
+            id,
+            self.state,
+            self.done,
+            self.total,
+            self.rate,
+            self.tables_done,
+            self.tables_total,
+            self.cracked.len()
+        )
+    }
+}
+
+// Progress hook that keeps a job's entry in the job list up to date
+struct JobProgress {
+    id: u64,
+    jobs: Jobs,
+}
+
+impl Progress for JobProgress {
+    fn update(&self, done: u64, total: u64, rate: f64) {
+        if let Ok(mut jobs) = self.jobs.lock()
+            && let Some(job) = jobs.get_mut(&self.id)
+        {
+            job.done = done;
+            job.total = total;
+            job.rate = rate;
+        }
+    }
+}
+
+// Crack sink that records found passwords in the job list and passes them on
+struct JobResults {
+    id: u64,
+    jobs: Jobs,
+    sender: Option<UnboundedSender<CrackResult>>,
+    // Set while the last table runs, whose misses are final
+    last_table: AtomicBool,
+    // Misses already sent
+    missed: std::sync::Mutex<HashSet<String>>,
+}
+
+impl JobResults {
+    fn send(&self, hash: &str, password: Option<&str>) {
+        if let Some(sender) = &self.sender {
+            // The receiver is gone when a streaming client disconnects, the job goes on
+            let _ = sender.send((hash.to_string(), password.map(str::to_string)));
+        }
+    }
+}
+
+impl CrackSink for JobResults {
+    fn result(&self, hash: &str, password: Option<&str>) {
+        let Some(password) = password else {
+            if self.last_table.load(Ordering::Relaxed) {
+                if let Ok(mut missed) = self.missed.lock() {
+                    missed.insert(hash.to_string());
+                }
+                self.send(hash, None);
+            }
+            return;
+        };
+        if let Ok(mut jobs) = self.jobs.lock()
+            && let Some(job) = jobs.get_mut(&self.id)
+        {
+            job.cracked.push((hash.to_string(), password.to_string()));
+        }
+        self.send(hash, Some(password));
+    }
+}
+
+// Registers a job and starts it, returns its id and the task that ends with its final
+// state and result. With a sender, every result is also sent down it as soon as it is known
+// Refused with TooLarge while MAX_PENDING_JOBS jobs have not finished
+pub(crate) fn submit(
+    jobs: &Jobs,
+    pool: &Arc<ComputePool>,
+    hashes: HashFile,
+    table_data: Vec<(String, Arc<TableData>)>,
+    cache: SharedCache,
+    sender: Option<UnboundedSender<CrackResult>>,
+    owner: Option<usize>,
+) -> Result<(u64, JobTask), Response> {
+    let cancel = CancelToken::new();
+    let id = {
+        let Ok(mut map) = jobs.lock() else {
+            return Err(message(Status::InternalError, "Job list is unavailable"));
+        };
+        let pending = map.values().filter(|job| !job.finished()).count();
+        if pending >= MAX_PENDING_JOBS {
+            error!("Refused crack job, {} jobs are pending", pending);
+            return Err(message(
+                Status::TooLarge,
+                &format!(
+                    "{} jobs are queued or running, the limit is {}, try again later",
+                    pending, MAX_PENDING_JOBS
+                ),
+            ));
+        }
+        let id = NEXT_JOB_ID.fetch_add(1, Ordering::Relaxed);
+        map.insert(id, Job::new(owner, table_data.len(), cancel.clone()));
+        id
+    };
+    info!(
+        "Queued crack job {} of {} hashes, using tables {:?}",
+        id,
+        hashes.records.len(),
+        table_data.iter().map(|(name, _)| name).collect::<Vec<_>>()
+    );
+
+    let results = JobResults {
+        id,
+        jobs: Arc::clone(jobs),
+        sender,
+        last_table: AtomicBool::new(false),
+        missed: std::sync::Mutex::new(HashSet::new()),
+    };
+    let jobs = Arc::clone(jobs);
+    let pool = Arc::clone(pool);
+    let handle = tokio::spawn(async move {
+        let job_jobs = Arc::clone(&jobs);
+        let job_cancel = cancel.clone();
+        let outcome = pool
+            .run(move || {
+                crack_job(
+                    id,
+                    hashes,
+                    &table_data,
+                    &job_jobs,
+                    cache,
+                    job_cancel,
+                    results,
+                )
+            })
+            .await
+            .and_then(|outcome| outcome);
+
+        let cancelled = cancel.is_cancelled();
+        let (state, response) = match outcome {
+            Ok(Some(output)) if cancelled => ("cancelled", (Status::Ok, output)),
+            Ok(Some(output)) => ("done", (Status::Ok, output)),
+            Ok(None) if cancelled => (
+                "cancelled",
+                message(
+                    Status::NotFound,
+                    &format!("Job {} was cancelled before cracking anything", id),
+                ),
+            ),
+            // Running cleanly without a result is not a failure, only the answer is NotFound
+            Ok(None) => ("done", message(Status::NotFound, "No passwords cracked")),
+            Err(e) => {
+                error!("Crack job {} failed: {:?}", id, e);
+                (
+                    "failed",
+                    message(Status::InternalError, &format!("Job {} failed: {}", id, e)),
+                )
+            }
+        };
+        info!("Crack job {} {}", id, state);
+        finish(&jobs, id, state, response.clone());
+        (state, response)
+    });
+    Ok((id, handle))
+}
+
+// Records a job's result and forgets the oldest finished jobs beyond FINISHED_JOBS_KEPT
+fn finish(jobs: &Jobs, id: u64, state: &'static str, response: Response) {
+    let Ok(mut map) = jobs.lock() else {
+        return;
+    };
+    if let Some(job) = map.get_mut(&id) {
+        job.state = state;
+        job.result = Some(response);
+    }
+    let mut finished: Vec<u64> = map
+        .iter()
+        .filter(|(_, job)| job.finished())
+        .map(|(id, _)| *id)
+        .collect();
+    if finished.len() > FINISHED_JOBS_KEPT {
+        finished.sort();
+        for id in &finished[..finished.len() - FINISHED_JOBS_KEPT] {
+            map.remove(id);
+        }
+    }
+}
+
+// Runs the hashes against every table, each table only sees what the earlier ones missed
+// Returns one line per hash in the order of the hash file, or None if nothing was cracked
+// A cancelled job stops after the hash it is on and returns what it has
+fn crack_job(
+    job_id: u64,
+    hashes: HashFile,
+    table_data: &[(String, Arc<TableData>)],
+    jobs: &Jobs,
+    cache: SharedCache,
+    cancel: CancelToken,
+    results: JobResults,
+) -> std::io::Result<Option<Vec<u8>>> {
+    if let Ok(mut map) = jobs.lock()
+        && let Some(job) = map.get_mut(&job_id)
+    {
+        job.state = "running";
+    }
+    let progress = JobProgress {
+        id: job_id,
+        jobs: Arc::clone(jobs),
+    };
+    let results = Arc::new(results);
+    let hooks = Hooks::new(Arc::new(progress), cancel.clone()).with_results(results.clone());
+
+    let mut cracked: HashMap<String, String> = HashMap::new();
+    let mut remaining = hashes.clone();
+    for (index, (name, table)) in table_data.iter().enumerate() {
+        if remaining.records.is_empty() || cancel.is_cancelled() {
+            break;
+        }
+        if index + 1 == table_data.len() {
+            results.last_table.store(true, Ordering::Relaxed);
+        }
+        // The table is cracked where it lies, mapped or on the heap, a cancelled crack
+        // still returns what it found before it stopped. Tables were picked to fit the
+        // hashes, so any error here is ours and fails the job
+        let found = crack_table(&table[..], &remaining, cache.as_ref(), &hooks)
+            .map_err(|e| std::io::Error::other(format!("table '{}': {}", name, e)))?;
+        let before = cracked.len();
+        for (hash, password) in found {
+            if let Some(password) = password {
+                cracked.insert(hash, password);
+            }
+        }
+        info!(
+            "Table '{}' cracked {} of {} hashes",
+            name,
+            cracked.len() - before,
+            remaining.records.len()
+        );
+        remaining
+            .records
+            .retain(|(_, digest)| !cracked.contains_key(&hex::encode(digest)));
+
+        if let Ok(mut map) = jobs.lock()
+            && let Some(job) = map.get_mut(&job_id)
+        {
+            job.tables_done += 1;
+        }
+    }
+
+    // Whatever is left has been missed by every table, unless the job was cut short
+    if !cancel.is_cancelled()
+        && let Ok(missed) = results.missed.lock()
+    {
+        for (_, digest) in &remaining.records {
+            let hash = hex::encode(digest);
+            if !missed.contains(&hash) {
+                results.send(&hash, None);
+            }
+        }
+    }
+
+    if cracked.is_empty() {
+        return Ok(None);
+    }
+
+    let mut output = String::new();
+    for (_, digest) in &hashes.records {
+        let hash = hex::encode(digest);
+        let password = cracked.get(&hash).map_or("NOT FOUND", String::as_str);
+        output.push_str(&format!("{}\t{}\n", hash, password));
+    }
+    Ok(Some(output.into_bytes()))
+}
+
+// Status handler
+// v1 requests list every job the caller may see, one line each: id, state, done/total of the current table,
+// rate, tables done and passwords cracked so far
+// v2 requests carry a job id u64 and get that job's line followed by the hash and password
+// of everything it has cracked so far
+pub(crate) async fn handle_status(
+    stream: &mut TcpStream,
+    jobs: &Jobs,
+    caller: Caller,
+) -> std::io::Result<Response> {
+    let mut version = [0u8; 1];
+    stream.read_exact(&mut version).await?;
+    let id = match version[0] {
+        1 => None,
+        2 => Some(stream.read_u64().await?),
+        version => {
+            return Ok(message(
+                Status::BadRequest,
+                &format!("Unsupported status request version: {}", version),
+            ));
+        }
+    };
+
+    let Ok(map) = jobs.lock() else {
+        return Ok(message(Status::InternalError, "Job list is unavailable"));
+    };
+    let mut lines = String::new();
+    match id {
+        Some(id) => {
+            let Some(job) = map.get(&id).filter(|job| caller.may_see(job.owner)) else {
+                return Ok(no_such_job(id));
+            };
+            lines.push_str(&job.status_line(id));
+            for (hash, password) in &job.cracked {
+                lines.push_str(&format!("{}\t{}\n", hash, password));
+            }
+        }
+        None => {
+            let mut visible: Vec<(&u64, &Job)> = map
+                .iter()
+                .filter(|(_, job)| caller.may_see(job.owner))
+                .collect();
+            visible.sort_by_key(|(id, _)| **id);
+            for (id, job) in visible {
+                lines.push_str(&job.status_line(*id));
+            }
+            if lines.is_empty() {
+                lines.push_str("No jobs\n");
+            }
+        }
+    }
+
+    Ok((Status::Ok, lines.into_bytes()))
+}
+
+// Result handler: version | job id u64
+// Answers as a crack request would have, or Pending while the job is still running
+pub(crate) async fn handle_result(
+    stream: &mut TcpStream,
+    jobs: &Jobs,
+    caller: Caller,
+) -> std::io::Result<Response> {
+    let mut version = [0u8; 1];
+    stream.read_exact(&mut version).await?;
+    let id = stream.read_u64().await?;
+
+    let Ok(map) = jobs.lock() else {
+        return Ok(message(Status::InternalError, "Job list is unavailable"));
+    };
+    Ok(match map.get(&id).filter(|job| caller.may_see(job.owner)) {
+        Some(job) => match &job.result {
+            Some(response) => response.clone(),
+            None => message(Status::Pending, &format!("Job {} is {}", id, job.state)),
+        },
+        None => no_such_job(id),
+    })
+}
+
+// Cancel handler: version | job id u64
+// The job stops after the hash it is on and keeps what it cracked so far
+pub(crate) async fn handle_cancel(
+    stream: &mut TcpStream,
+    jobs: &Jobs,
+    caller: Caller,
+) -> std::io::Result<Response> {
+    let mut version = [0u8; 1];
+    stream.read_exact(&mut version).await?;
+    let id = stream.read_u64().await?;
+
+    let Ok(map) = jobs.lock() else {
+        return Ok(message(Status::InternalError, "Job list is unavailable"));
+    };
+    Ok(match map.get(&id).filter(|job| caller.may_see(job.owner)) {
+        Some(job) if job.finished() => message(
+            Status::BadRequest,
+            &format!("Job {} has already finished ({})", id, job.state),
+        ),
+        Some(job) => {
+            job.cancel.cancel();
+            info!("Cancelling crack job {}", id);
+            message(Status::Ok, &format!("Cancelling job {}", id))
+        }
+        None => no_such_job(id),
+    })
+}
+
+fn no_such_job(id: u64) -> Response {
+    message(Status::NotFound, &format!("No job with id {}", id))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    type TestResult = Result<(), Box<dyn std::error::Error>>;
+
+    fn no_hashes() -> Result<HashFile, Box<dyn std::error::Error>> {
+        HashFile::new("md5", 4, Vec::new())
+    }
+
+    // A job list holding `ids` jobs that have not finished
+    fn pending(ids: std::ops::Range<u64>) -> Result<Jobs, Box<dyn std::error::Error>> {
+        let jobs: Jobs = Arc::new(std::sync::Mutex::new(HashMap::new()));
+        let mut map = jobs.lock().map_err(|e| e.to_string())?;
+        for id in ids {
+            map.insert(id, Job::new(None, 1, CancelToken::new()));
+        }
+        drop(map);
+        Ok(jobs)
+    }
+
+    #[tokio::test]
+    async fn jobs_beyond_the_pending_limit_are_refused() -> TestResult {
+        let jobs = pending(0..MAX_PENDING_JOBS as u64)?;
+        let pool = Arc::new(ComputePool::new(1)?);
+        let refused = submit(&jobs, &pool, no_hashes()?, Vec::new(), None, None, None);
+        assert!(matches!(refused, Err((Status::TooLarge, _))));
+
+        // Once one of them finishes there is room again
+        finish(&jobs, 0, "done", message(Status::Ok, "done"));
+        let (id, task) = submit(&jobs, &pool, no_hashes()?, Vec::new(), None, None, None)
+            .map_err(|(_, refusal)| String::from_utf8_lossy(&refusal).to_string())?;
+        let (state, (status, _)) = task.await?;
+        assert_eq!((state, status), ("done", Status::NotFound));
+        assert!(jobs.lock().map_err(|e| e.to_string())?[&id].finished());
+        Ok(())
+    }
+
+    #[test]
+    fn only_the_newest_finished_jobs_are_kept() -> TestResult {
+        let extra = 10;
+        let total = (FINISHED_JOBS_KEPT + extra) as u64;
+        // Job 0 never finishes, so it is never forgotten
+        let jobs = pending(0..total + 1)?;
+        for id in 1..=total {
+            finish(&jobs, id, "done", message(Status::Ok, "done"));
+        }
+
+        let map = jobs.lock().map_err(|e| e.to_string())?;
+        assert_eq!(map.len(), FINISHED_JOBS_KEPT + 1);
+        assert!(map.contains_key(&0));
+        assert!((1..=extra as u64).all(|id| !map.contains_key(&id)));
+        assert!((extra as u64 + 1..=total).all(|id| map[&id].finished()));
+        Ok(())
+    }
+}
@@ -1,156 +1,1148 @@
-use hashassin_core::crack;
+use auth::{Caller, Session};
+use chunked::Uploads;
+use hashassin_core::{
+    HashFile, Status, TableInfo, UPLOAD_FLAG_REPLACE, encode_response, encode_response_header,
+    load_tokens,
+};
+use jobs::Jobs;
+use pool::ComputePool;
 use std::collections::HashMap;
-use std::io::{Read, Write};
-use std::net::{TcpListener, TcpStream};
+use std::io::ErrorKind;
 use std::sync::Arc;
-use std::thread;
+use std::time::Duration;
+use store::{TableData, TableStore};
 use stretto::Cache;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
 use tracing::{error, info};
+use validate::TableValidator;
+
+mod auth;
+mod chunked;
+mod jobs;
+mod pool;
+mod store;
+mod validate;
 
 // Type aliases for clarity
-type RainbowTables = Arc<std::sync::Mutex<HashMap<String, Vec<u8>>>>;
+type RainbowTables = Arc<std::sync::Mutex<TableStore>>;
 type SharedCache = Option<Cache<String, String>>;
+// Status and payload of the frame sent back for a request
+type Response = (Status, Vec<u8>);
+
+// Uploads are read and validated in pieces of this size
+const READ_CHUNK: usize = 64 * 1024;
+// How long and how much of a refused request body is drained before closing
+const LINGER_TIME: Duration = Duration::from_secs(2);
+const LINGER_BYTES: usize = 64 * 1024 * 1024;
+
+// Everything start_server needs to know
+#[derive(Debug, Clone)]
+pub struct ServerConfig {
+    pub address: String,
+    // Workers in the pool that runs crack jobs
+    pub compute_threads: usize,
+    // Worker threads of the tokio runtime that handles connections
+    pub async_threads: usize,
+    pub cache_size: Option<i32>,
+    // Tables are kept here across restarts when given
+    pub data_dir: Option<String>,
+    // Largest table upload accepted, in bytes
+    pub max_upload_size: u64,
+    // Largest hash file a crack request may carry, in bytes
+    pub max_crack_size: u64,
+    // Connections must authenticate with a token from this file when given
+    pub auth_token_file: Option<String>,
+}
 
 // Server start function
-pub fn start_server(
-    address: &str,
-    compute_threads: usize,
-    _async_threads: usize,
-    cache_size: Option<i32>,
+// Connections are served on a tokio runtime with `async_threads` workers, cracking runs on a
+// separate pool of `compute_threads` OS threads
+pub fn start_server(config: &ServerConfig) -> Result<(), Box<dyn std::error::Error>> {
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .worker_threads(config.async_threads.max(1))
+        .thread_name("hashassin-io")
+        .enable_all()
+        .build()?;
+    runtime.block_on(serve(config))
+}
+
+async fn serve(config: &ServerConfig) -> Result<(), Box<dyn std::error::Error>> {
+    let listener = TcpListener::bind(config.address.as_str()).await?;
+    serve_on(listener, config).await
+}
+
+// Serves connections from a bound listener until the process ends
+async fn serve_on(
+    listener: TcpListener,
+    config: &ServerConfig,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    let listener = TcpListener::bind(address)?;
-    let tables: RainbowTables = Arc::new(std::sync::Mutex::new(HashMap::new()));
+    let address = listener.local_addr()?;
+    let compute_threads = config.compute_threads;
+    let cache_size = config.cache_size;
+    let (max_upload_size, max_crack_size) = (config.max_upload_size, config.max_crack_size);
+    let tables: RainbowTables = Arc::new(std::sync::Mutex::new(TableStore::open(
+        config.data_dir.as_deref(),
+    )?));
+    let jobs: Jobs = Arc::new(std::sync::Mutex::new(HashMap::new()));
+    let uploads: Uploads = Arc::new(std::sync::Mutex::new(HashMap::new()));
+    tokio::spawn(chunked::expire_idle(Arc::clone(&uploads)));
+    let pool = Arc::new(ComputePool::new(compute_threads)?);
+    let tokens = Arc::new(match &config.auth_token_file {
+        Some(path) => load_tokens(path)?,
+        None => Vec::new(),
+    });
 
     // Using stretto cache directly without Arc<Mutex<>> as recommended
-    let cache: SharedCache =
-        cache_size.map(|size| Cache::new(512, size as i64).expect("Failed to create cache"));
+    let cache: SharedCache = match cache_size {
+        Some(size) => Some(
+            Cache::new(512, size as i64).map_err(|e| format!("Failed to create cache: {:?}", e))?,
+        ),
+        None => None,
+    };
 
     info!("Server listening on {}", address);
     info!(
-        "Using compute_threads = {}, cache_size = {:?}",
-        compute_threads, cache_size
+        "Using compute_threads = {}, cache_size = {:?}, max_upload_size = {}, max_crack_size = {}",
+        compute_threads, cache_size, max_upload_size, max_crack_size
     );
+    if tokens.is_empty() {
+        info!("No token file given, every connection may run every command");
+    } else {
+        info!("Accepting {} tokens", tokens.len());
+    }
 
-    for stream in listener.incoming() {
+    loop {
+        let (mut stream, peer) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(e) => {
+                error!("Failed to accept connection: {:?}", e);
+                continue;
+            }
+        };
         let tables = Arc::clone(&tables);
+        let jobs = Arc::clone(&jobs);
+        let pool = Arc::clone(&pool);
         let cache = cache.clone();
 
-        if let Ok(mut stream) = stream {
-            thread::spawn(move || {
+        let uploads = Arc::clone(&uploads);
+        let mut session = Session::new(Arc::clone(&tokens));
+
+        // A connection may carry any number of requests, one after the other, until the
+        // client closes it or a request is refused
+        tokio::spawn(async move {
+            let mut served = 0u64;
+            loop {
                 let mut magic_buf = [0u8; 6];
-                if let Err(e) = stream.read_exact(&mut magic_buf) {
-                    error!("Failed to read magic word: {:?}", e);
+                if let Err(e) = stream.read_exact(&mut magic_buf).await {
+                    if served == 0 || e.kind() != ErrorKind::UnexpectedEof {
+                        error!("Failed to read magic word from {}: {:?}", peer, e);
+                    }
                     return;
                 }
+                served += 1;
 
                 let magic_str = String::from_utf8_lossy(&magic_buf);
-                match magic_str.as_ref() {
-                    "upload" => {
-                        if let Err(e) = handle_upload(&mut stream, &tables) {
-                            error!("Upload failed: {:?}", e);
+                let response = if let Some(refusal) = session.check(&magic_str) {
+                    Ok(refusal)
+                } else {
+                    match magic_str.as_ref() {
+                        "challg" => session.handle_challenge(&mut stream).await,
+                        "auth\0\0" => session.handle_auth(&mut stream).await,
+                        "upload" => handle_upload(&mut stream, &tables, max_upload_size).await,
+                        "upbegn" => {
+                            chunked::handle_begin(&mut stream, &tables, &uploads, max_upload_size)
+                                .await
                         }
-                    }
-                    "crack\u{0}" => {
-                        if let Err(e) = handle_crack_sync(&mut stream, &tables, cache.clone()) {
-                            error!("Crack failed: {:?}", e);
+                        "upchnk" => chunked::handle_chunk(&mut stream, &uploads).await,
+                        "upoffs" => chunked::handle_offset(&mut stream, &uploads).await,
+                        "updone" => chunked::handle_done(&mut stream, &tables, &uploads).await,
+                        "crack\u{0}" => {
+                            handle_crack(
+                                &mut stream,
+                                &tables,
+                                &jobs,
+                                &pool,
+                                cache.clone(),
+                                max_crack_size,
+                                session.caller(),
+                            )
+                            .await
+                        }
+                        "submit" => {
+                            handle_submit(
+                                &mut stream,
+                                &tables,
+                                &jobs,
+                                &pool,
+                                cache.clone(),
+                                max_crack_size,
+                                session.caller(),
+                            )
+                            .await
+                        }
+                        "status" => jobs::handle_status(&mut stream, &jobs, session.caller()).await,
+                        "result" => jobs::handle_result(&mut stream, &jobs, session.caller()).await,
+                        "cancel" => jobs::handle_cancel(&mut stream, &jobs, session.caller()).await,
+                        "list\0\0" => handle_list(&mut stream, &tables).await,
+                        "info\0\0" => handle_info(&mut stream, &tables).await,
+                        "delete" => handle_delete(&mut stream, &tables).await,
+                        "downld" => match handle_download(&mut stream, &tables).await {
+                            Ok(Some(response)) => Ok(response),
+                            // The table went out straight from its bytes
+                            Ok(None) => continue,
+                            Err(e) => Err(e),
+                        },
+                        _ => {
+                            error!("Unknown command: {:?}", magic_buf);
+                            Ok(message(Status::BadRequest, "Unknown command"))
                         }
                     }
-                    _ => {
-                        error!("Unknown command: {:?}", magic_buf);
-                    }
+                };
+                let (status, payload) = response.unwrap_or_else(|e| {
+                    error!(
+                        "{} from {} failed: {:?}",
+                        magic_str.trim_end_matches('\0'),
+                        peer,
+                        e
+                    );
+                    failure(e)
+                });
+
+                if let Err(e) = stream.write_all(&encode_response(status, &payload)).await {
+                    error!("Failed to send response to {}: {:?}", peer, e);
+                    return;
                 }
-            });
-        }
+                if status != Status::Ok {
+                    linger(&mut stream).await;
+                    return;
+                }
+            }
+        });
     }
-
-    Ok(())
 }
 
 // Upload handler
-fn handle_upload(stream: &mut TcpStream, tables: &RainbowTables) -> std::io::Result<()> {
+// v1 requests are version | name_len | name | payload_len u64 | table, v2 requests add a
+// flags byte after the version, FLAG_REPLACE allows overwriting a table of the same name
+async fn handle_upload(
+    stream: &mut TcpStream,
+    tables: &RainbowTables,
+    max_size: u64,
+) -> std::io::Result<Response> {
     let mut version = [0u8; 1];
-    let mut name_len = [0u8; 1];
-
-    stream.read_exact(&mut version)?;
-    stream.read_exact(&mut name_len)?;
-
-    let mut name = vec![0u8; name_len[0] as usize];
-    stream.read_exact(&mut name)?;
+    stream.read_exact(&mut version).await?;
+    let mut flags = [0u8; 1];
+    match version[0] {
+        1 => {}
+        2 => {
+            stream.read_exact(&mut flags).await?;
+        }
+        version => {
+            return Ok(message(
+                Status::BadRequest,
+                &format!("Unsupported upload request version: {}", version),
+            ));
+        }
+    }
+    let replace = flags[0] & UPLOAD_FLAG_REPLACE != 0;
+    let name_str = read_name(stream).await?;
 
     let mut size = [0u8; 8];
-    stream.read_exact(&mut size)?;
-
+    stream.read_exact(&mut size).await?;
     let payload_len = u64::from_be_bytes(size);
-    let mut payload = vec![0u8; payload_len as usize];
-    stream.read_exact(&mut payload)?;
 
-    let name_str = String::from_utf8_lossy(&name).to_string();
+    if let Some(refusal) = check_upload(tables, &name_str, payload_len, replace, max_size) {
+        return Ok(refusal);
+    }
+
+    let mut validator = TableValidator::new(payload_len);
+    let mut payload = Vec::new();
+    let mut chunk = vec![0u8; READ_CHUNK];
+    while (payload.len() as u64) < payload_len {
+        let want = READ_CHUNK.min((payload_len - payload.len() as u64) as usize);
+        stream.read_exact(&mut chunk[..want]).await?;
+        if let Err(e) = validator.feed(&chunk[..want]) {
+            error!("Rejected rainbow table '{}': {}", name_str, e);
+            return Ok(message(
+                Status::BadRequest,
+                &format!("Rejected rainbow table '{}': {}", name_str, e),
+            ));
+        }
+        payload.extend_from_slice(&chunk[..want]);
+    }
+    if let Err(e) = validator.finish() {
+        return Ok(message(
+            Status::BadRequest,
+            &format!("Rejected rainbow table '{}': {}", name_str, e),
+        ));
+    }
+
     info!(
         "Received rainbow table '{}' ({} bytes)",
         name_str, payload_len
     );
 
-    if let Ok(mut map) = tables.lock() {
-        map.insert(name_str, payload);
+    // Refuse tables whose header or body checksum does not match
+    let info = match TableInfo::from_table(&name_str, &payload) {
+        Ok(info) => info,
+        Err(e) => {
+            error!("Rejected rainbow table '{}': {}", name_str, e);
+            return Ok(message(
+                Status::BadRequest,
+                &format!("Rejected rainbow table '{}': {}", name_str, e),
+            ));
+        }
+    };
+
+    // Writing to the data directory blocks, so it stays off the async workers, and happens
+    // before the store is locked so other requests can still read it meanwhile
+    let tables = Arc::clone(tables);
+    tokio::task::spawn_blocking(move || {
+        let Ok(stager) = tables.lock().map(|store| store.stager()) else {
+            return Ok(message(Status::InternalError, "Table store is unavailable"));
+        };
+        let staged = stager.bytes(payload)?;
+        let Ok(mut store) = tables.lock() else {
+            staged.discard();
+            return Ok(message(Status::InternalError, "Table store is unavailable"));
+        };
+        if store.contains(&name_str) && !replace {
+            staged.discard();
+            return Ok(name_taken(&name_str));
+        }
+        store.insert(info, staged)?;
+        Ok(message(
+            Status::Ok,
+            &format!("Stored rainbow table '{}'", name_str),
+        ))
+    })
+    .await?
+}
+
+// Everything about an upload that can be decided before the body arrives, so bad uploads
+// fail fast
+fn check_upload(
+    tables: &RainbowTables,
+    name: &str,
+    size: u64,
+    replace: bool,
+    max_size: u64,
+) -> Option<Response> {
+    if size > max_size {
+        return Some(message(
+            Status::TooLarge,
+            &format!(
+                "Rainbow table '{}' is {} bytes, the limit is {}",
+                name, size, max_size
+            ),
+        ));
+    }
+    // Names end up in tab separated listings
+    if name.is_empty() || name.chars().any(char::is_control) {
+        return Some(message(
+            Status::BadRequest,
+            &format!("Invalid table name '{}'", name.escape_debug()),
+        ));
+    }
+    if !replace && tables.lock().is_ok_and(|store| store.contains(name)) {
+        return Some(name_taken(name));
+    }
+    None
+}
+
+// List handler, one TableInfo line per table sorted by name
+async fn handle_list(stream: &mut TcpStream, tables: &RainbowTables) -> std::io::Result<Response> {
+    let mut version = [0u8; 1];
+    stream.read_exact(&mut version).await?;
+
+    let Ok(store) = tables.lock() else {
+        return Ok(message(Status::InternalError, "Table store is unavailable"));
+    };
+    let mut infos: Vec<&TableInfo> = store.tables().map(|table| &table.info).collect();
+    infos.sort_by(|a, b| a.name.cmp(&b.name));
+    let lines: String = infos
+        .iter()
+        .map(|info| format!("{}\n", info.to_line()))
+        .collect();
+    Ok((Status::Ok, lines.into_bytes()))
+}
+
+// Info handler: version | name_len | name, answers with the table's TableInfo line
+async fn handle_info(stream: &mut TcpStream, tables: &RainbowTables) -> std::io::Result<Response> {
+    let mut version = [0u8; 1];
+    stream.read_exact(&mut version).await?;
+    let name = read_name(stream).await?;
+
+    let Ok(store) = tables.lock() else {
+        return Ok(message(Status::InternalError, "Table store is unavailable"));
+    };
+    Ok(match store.get(&name) {
+        Some(table) => message(Status::Ok, &table.info.to_line()),
+        None => no_such_table(&name),
+    })
+}
+
+// Delete handler: version | name_len | name
+async fn handle_delete(
+    stream: &mut TcpStream,
+    tables: &RainbowTables,
+) -> std::io::Result<Response> {
+    let mut version = [0u8; 1];
+    stream.read_exact(&mut version).await?;
+    let name = read_name(stream).await?;
+
+    let tables = Arc::clone(tables);
+    tokio::task::spawn_blocking(move || {
+        let Ok(mut store) = tables.lock() else {
+            return Ok(message(Status::InternalError, "Table store is unavailable"));
+        };
+        Ok(if store.remove(&name)? {
+            info!("Deleted rainbow table '{}'", name);
+            message(Status::Ok, &format!("Deleted rainbow table '{}'", name))
+        } else {
+            no_such_table(&name)
+        })
+    })
+    .await?
+}
+
+// Download handler: version | name_len | name, answers with the table file
+// Returns None once the table has been sent
+async fn handle_download(
+    stream: &mut TcpStream,
+    tables: &RainbowTables,
+) -> std::io::Result<Option<Response>> {
+    let mut version = [0u8; 1];
+    stream.read_exact(&mut version).await?;
+    let name = read_name(stream).await?;
+
+    // The store is only locked to find the table, it is sent from its mapping or heap
+    // bytes directly, never copied
+    let data = match tables.lock() {
+        Ok(store) => store.get(&name).map(|table| Arc::clone(&table.data)),
+        Err(_) => {
+            return Ok(Some(message(
+                Status::InternalError,
+                "Table store is unavailable",
+            )));
+        }
+    };
+    let Some(data) = data else {
+        return Ok(Some(no_such_table(&name)));
+    };
+    stream
+        .write_all(&encode_response_header(Status::Ok, data.len() as u64))
+        .await?;
+    for chunk in data.chunks(READ_CHUNK) {
+        stream.write_all(chunk).await?;
     }
+    Ok(None)
+}
 
-    Ok(())
+// Reads a table name sent as name_len u8 | name
+async fn read_name(stream: &mut TcpStream) -> std::io::Result<String> {
+    let mut name_len = [0u8; 1];
+    stream.read_exact(&mut name_len).await?;
+    let mut name = vec![0u8; name_len[0] as usize];
+    stream.read_exact(&mut name).await?;
+    Ok(String::from_utf8_lossy(&name).to_string())
+}
+
+fn name_taken(name: &str) -> Response {
+    message(
+        Status::BadRequest,
+        &format!(
+            "A rainbow table named '{}' already exists, upload with replace to overwrite it",
+            name
+        ),
+    )
 }
 
-// Crack handler
-fn handle_crack_sync(
+fn no_such_table(name: &str) -> Response {
+    message(
+        Status::NotFound,
+        &format!("No rainbow table named '{}'", name),
+    )
+}
+
+// Crack handler, the cracking itself runs as a job on the compute pool and the connection
+// waits for it
+// v1 requests use every compatible table, v2 requests may name the tables to use:
+//   version | table_count u8 | [name_len u8 | name]* | payload_len u64 | hash file
+// v3 requests look like v2 ones and have their results streamed as they are found
+async fn handle_crack(
     stream: &mut TcpStream,
     tables: &RainbowTables,
+    jobs: &Jobs,
+    pool: &Arc<ComputePool>,
     cache: SharedCache,
-) -> std::io::Result<()> {
+    max_size: u64,
+    caller: Caller,
+) -> std::io::Result<Response> {
     let mut version = [0u8; 1];
-    let mut size = [0u8; 8];
+    stream.read_exact(&mut version).await?;
+    let job = match version[0] {
+        1 => read_crack_job(stream, tables, Vec::new(), max_size).await?,
+        2 | 3 => {
+            let names = read_table_names(stream).await?;
+            read_crack_job(stream, tables, names, max_size).await?
+        }
+        version => {
+            return Ok(message(
+                Status::BadRequest,
+                &format!("Unsupported crack request version: {}", version),
+            ));
+        }
+    };
+    let (hashes, table_data) = match job {
+        Ok(job) => job,
+        Err(response) => return Ok(response),
+    };
+    if version[0] == 3 {
+        return stream_crack(stream, jobs, pool, hashes, table_data, cache, caller).await;
+    }
+
+    let (_, job) = match jobs::submit(jobs, pool, hashes, table_data, cache, None, caller.owner()) {
+        Ok(job) => job,
+        Err(refusal) => return Ok(refusal),
+    };
+    let (_, response) = job.await.map_err(std::io::Error::other)?;
+    Ok(response)
+}
+
+// Sends a More frame per hash as the job cracks it or every table misses it, and returns
+// the summary frame
+async fn stream_crack(
+    stream: &mut TcpStream,
+    jobs: &Jobs,
+    pool: &Arc<ComputePool>,
+    hashes: HashFile,
+    table_data: Vec<(String, Arc<TableData>)>,
+    cache: SharedCache,
+    caller: Caller,
+) -> std::io::Result<Response> {
+    let total = hashes.records.len();
+    let (sender, mut receiver) = tokio::sync::mpsc::unbounded_channel();
+    let (id, job) = match jobs::submit(
+        jobs,
+        pool,
+        hashes,
+        table_data,
+        cache,
+        Some(sender),
+        caller.owner(),
+    ) {
+        Ok(job) => job,
+        Err(refusal) => return Ok(refusal),
+    };
+
+    // The channel closes when the job ends, a client that goes away leaves the job running
+    let mut cracked = 0;
+    while let Some((hash, password)) = receiver.recv().await {
+        if password.is_some() {
+            cracked += 1;
+        }
+        let line = format!("{}\t{}", hash, password.as_deref().unwrap_or("NOT FOUND"));
+        stream
+            .write_all(&encode_response(Status::More, line.as_bytes()))
+            .await?;
+    }
+
+    let (state, response) = job.await.map_err(std::io::Error::other)?;
+    Ok(match response.0 {
+        // Finding nothing is a result like any other once every hash has been reported
+        Status::Ok | Status::NotFound => message(
+            Status::Ok,
+            &format!(
+                "Job {} {}, cracked {} of {} hashes",
+                id, state, cracked, total
+            ),
+        ),
+        _ => response,
+    })
+}
+
+// Submit handler, starts a crack job and answers with its id right away
+// Requests are version | then the rest of a v2 crack request
+async fn handle_submit(
+    stream: &mut TcpStream,
+    tables: &RainbowTables,
+    jobs: &Jobs,
+    pool: &Arc<ComputePool>,
+    cache: SharedCache,
+    max_size: u64,
+    caller: Caller,
+) -> std::io::Result<Response> {
+    let mut version = [0u8; 1];
+    stream.read_exact(&mut version).await?;
+    if version[0] != 1 {
+        return Ok(message(
+            Status::BadRequest,
+            &format!("Unsupported submit request version: {}", version[0]),
+        ));
+    }
+    let names = read_table_names(stream).await?;
+    let (hashes, table_data) = match read_crack_job(stream, tables, names, max_size).await? {
+        Ok(job) => job,
+        Err(response) => return Ok(response),
+    };
+
+    let (id, _) = match jobs::submit(jobs, pool, hashes, table_data, cache, None, caller.owner()) {
+        Ok(job) => job,
+        Err(refusal) => return Ok(refusal),
+    };
+    Ok((Status::Ok, id.to_be_bytes().to_vec()))
+}
 
-    stream.read_exact(&mut version)?;
-    stream.read_exact(&mut size)?;
+// Reads table_count u8 | [name_len u8 | name]*
+async fn read_table_names(stream: &mut TcpStream) -> std::io::Result<Vec<String>> {
+    let mut count = [0u8; 1];
+    stream.read_exact(&mut count).await?;
+    let mut names = Vec::new();
+    for _ in 0..count[0] {
+        names.push(read_name(stream).await?);
+    }
+    Ok(names)
+}
 
+// Reads the hash file of a crack request and picks the tables to run it against
+async fn read_crack_job(
+    stream: &mut TcpStream,
+    tables: &RainbowTables,
+    names: Vec<String>,
+    max_size: u64,
+) -> std::io::Result<Result<(HashFile, Vec<(String, Arc<TableData>)>), Response>> {
+    let mut size = [0u8; 8];
+    stream.read_exact(&mut size).await?;
     let payload_len = u64::from_be_bytes(size);
+    if payload_len > max_size {
+        return Ok(Err(message(
+            Status::TooLarge,
+            &format!(
+                "Hash file is {} bytes, the limit is {}",
+                payload_len, max_size
+            ),
+        )));
+    }
     let mut payload = vec![0u8; payload_len as usize];
-    stream.read_exact(&mut payload)?;
+    stream.read_exact(&mut payload).await?;
 
+    let hashes = match HashFile::from_bytes(&payload) {
+        Ok(hashes) => hashes,
+        Err(e) => {
+            return Ok(Err(message(
+                Status::BadRequest,
+                &format!("Invalid hash file: {}", e),
+            )));
+        }
+    };
+    if hashes.is_salted() {
+        return Ok(Err(message(
+            Status::BadRequest,
+            "Salted hashes cannot be cracked with a rainbow table",
+        )));
+    }
     info!("Received crack request ({} bytes)", payload_len);
+    Ok(select_tables(tables, &names, &hashes).map(|table_data| (hashes, table_data)))
+}
 
-    let table_data: Vec<Vec<u8>> = {
-        let guard = tables.lock().unwrap();
-        guard.values().cloned().collect()
+// Picks the tables a crack request runs against, sorted by name
+// Named tables must exist and fit the hashes, otherwise every table that fits is used
+fn select_tables(
+    tables: &RainbowTables,
+    names: &[String],
+    hashes: &HashFile,
+) -> Result<Vec<(String, Arc<TableData>)>, Response> {
+    let store = tables
+        .lock()
+        .map_err(|_| message(Status::InternalError, "Table store is unavailable"))?;
+
+    let mut selected = Vec::new();
+    if names.is_empty() {
+        for table in store.tables() {
+            if table_fits(&table.info, hashes) {
+                selected.push((table.info.name.clone(), Arc::clone(&table.data)));
+            }
+        }
+        if selected.is_empty() {
+            return Err(message(
+                Status::NotFound,
+                &format!(
+                    "No uploaded rainbow table fits {} hashes of {} passwords",
+                    hashes.algorithm,
+                    describe_len(hashes.pwd_len)
+                ),
+            ));
+        }
+    } else {
+        for name in names {
+            let table = store.get(name).ok_or_else(|| no_such_table(name))?;
+            if !table_fits(&table.info, hashes) {
+                return Err(message(
+                    Status::BadRequest,
+                    &format!(
+                        "Rainbow table '{}' does not fit {} hashes of {} passwords",
+                        name,
+                        hashes.algorithm,
+                        describe_len(hashes.pwd_len)
+                    ),
+                ));
+            }
+            if !selected.iter().any(|(selected, _)| selected == name) {
+                selected.push((name.clone(), Arc::clone(&table.data)));
+            }
+        }
+    }
+
+    selected.sort_by(|a, b| a.0.cmp(&b.0));
+    Ok(selected)
+}
+
+// A table fits when it uses the same algorithm and covers the password length
+// Hash files of varying length (0) can be tried against any length
+fn table_fits(table: &TableInfo, hashes: &HashFile) -> bool {
+    table.algorithm.eq_ignore_ascii_case(&hashes.algorithm)
+        && (hashes.pwd_len == 0
+            || (table.min_len..=table.max_len).contains(&(hashes.pwd_len as usize)))
+}
+
+fn describe_len(pwd_len: u8) -> String {
+    if pwd_len == 0 {
+        "varying length".to_string()
+    } else {
+        format!("length {}", pwd_len)
+    }
+}
+
+// After refusing a request, gives the client a moment to read the answer before the
+// connection closes, discarding whatever request body it is still sending
+async fn linger(stream: &mut TcpStream) {
+    let _ = stream.shutdown().await;
+    let drain = async {
+        let mut sink = vec![0u8; READ_CHUNK];
+        let mut drained = 0;
+        while drained < LINGER_BYTES {
+            match stream.read(&mut sink).await {
+                Ok(0) | Err(_) => break,
+                Ok(n) => drained += n,
+            }
+        }
+    };
+    let _ = tokio::time::timeout(LINGER_TIME, drain).await;
+}
+
+// A response carrying a text message
+fn message(status: Status, text: &str) -> Response {
+    (status, text.as_bytes().to_vec())
+}
+
+// The response for a request that failed part way, truncated or malformed requests are the
+// client's fault, anything else is ours
+fn failure(e: std::io::Error) -> Response {
+    let status = match e.kind() {
+        ErrorKind::UnexpectedEof | ErrorKind::InvalidData => Status::BadRequest,
+        _ => Status::InternalError,
     };
+    message(status, &e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hashassin_client::{ClientConfig, ClientError, ClientResult};
+    use hashassin_core::{
+        ChainStarts, Hooks, RESPONSE_HEADER_LEN, Scope, TableParams, chunk_crc,
+        decode_response_header, gen_hashes, gen_passwords, gen_rainbow_table, sign_challenge,
+    };
+    use std::fs;
+    use std::io::{Read, Write};
+    use std::path::PathBuf;
+
+    type TestResult = Result<(), Box<dyn std::error::Error>>;
+
+    // Starts a server keeping its tables in the fixture on a free port in the background and
+    // returns its address
+    fn start_server(
+        fixture: &Fixture,
+        auth_token_file: Option<String>,
+    ) -> Result<String, Box<dyn std::error::Error>> {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0")?;
+        listener.set_nonblocking(true)?;
+        let address = listener.local_addr()?.to_string();
+        let config = ServerConfig {
+            address: address.clone(),
+            compute_threads: 2,
+            async_threads: 2,
+            cache_size: None,
+            data_dir: Some(fixture.path("data")),
+            max_upload_size: 1 << 24,
+            max_crack_size: 1 << 24,
+            auth_token_file,
+        };
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(2)
+            .enable_all()
+            .build()?;
+        std::thread::spawn(move || {
+            runtime.block_on(async {
+                let served = match TcpListener::from_std(listener) {
+                    Ok(listener) => serve_on(listener, &config).await,
+                    Err(e) => Err(e.into()),
+                };
+                if let Err(e) = served {
+                    error!("Test server stopped: {}", e);
+                }
+            })
+        });
+        Ok(address)
+    }
+
+    // A scratch directory holding a small md5 table of 4 character passwords and a hash
+    // file of the table's own seeds, every one of which the table cracks
+    struct Fixture {
+        dir: PathBuf,
+    }
+
+    impl Fixture {
+        fn new(name: &str) -> Result<Fixture, Box<dyn std::error::Error>> {
+            let dir = std::env::temp_dir().join(format!(
+                "hashassin-server-test-{}-{}",
+                std::process::id(),
+                name
+            ));
+            fs::create_dir_all(&dir)?;
+            let fixture = Fixture { dir };
+            let seeds = gen_passwords(4, 50, 1, Some(7))?;
+            fs::write(fixture.path("seeds.txt"), seeds.join("\n"))?;
+            let params = TableParams {
+                algorithm: "md5".to_string(),
+                num_links: 20,
+                threads: 1,
+                len_range: None,
+                compress: false,
+                dp_bits: None,
+                resume: false,
+                checkpoint_every: 1000,
+            };
+            gen_rainbow_table(
+                ChainStarts::SeedFile(&fixture.path("seeds.txt")),
+                &fixture.path("table.rainbow"),
+                &params,
+                &Hooks::default(),
+            )?;
+            gen_hashes(
+                &fixture.path("seeds.txt"),
+                &fixture.path("hashes.bin"),
+                "md5",
+                1,
+                &Hooks::default(),
+            )?;
+            Ok(fixture)
+        }
+
+        fn path(&self, file: &str) -> String {
+            self.dir.join(file).to_string_lossy().to_string()
+        }
+
+        fn table(&self) -> std::io::Result<Vec<u8>> {
+            fs::read(self.path("table.rainbow"))
+        }
+    }
+
+    impl Drop for Fixture {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.dir);
+        }
+    }
+
+    // Sends one raw request on a connection of its own and reads the response frame
+    fn request(address: &str, bytes: &[u8]) -> Result<Response, Box<dyn std::error::Error>> {
+        let mut stream = std::net::TcpStream::connect(address)?;
+        exchange(&mut stream, bytes)
+    }
 
-    std::fs::write("temp_hashes.bin", &payload)?;
-    let mut combined_results = Vec::new();
-    let mut found = false;
+    // Sends a request on an open connection and reads the response frame
+    fn exchange(
+        stream: &mut std::net::TcpStream,
+        bytes: &[u8],
+    ) -> Result<Response, Box<dyn std::error::Error>> {
+        stream.write_all(bytes)?;
+        let mut header = [0u8; RESPONSE_HEADER_LEN];
+        stream.read_exact(&mut header)?;
+        let (status, len) = decode_response_header(&header)?;
+        let mut payload = vec![0u8; len as usize];
+        stream.read_exact(&mut payload)?;
+        Ok((status, payload))
+    }
+
+    fn begin(name: &str, size: usize, file_crc: u32) -> Vec<u8> {
+        let mut buffer = b"upbegn".to_vec();
+        buffer.extend_from_slice(&[1, 0, name.len() as u8]);
+        buffer.extend_from_slice(name.as_bytes());
+        buffer.extend_from_slice(&(size as u64).to_be_bytes());
+        buffer.extend_from_slice(&file_crc.to_be_bytes());
+        buffer
+    }
+
+    fn chunk(id: u64, offset: u64, data: &[u8], crc: u32) -> Vec<u8> {
+        let mut buffer = b"upchnk\x01".to_vec();
+        buffer.extend_from_slice(&id.to_be_bytes());
+        buffer.extend_from_slice(&offset.to_be_bytes());
+        buffer.extend_from_slice(&(data.len() as u32).to_be_bytes());
+        buffer.extend_from_slice(&crc.to_be_bytes());
+        buffer.extend_from_slice(data);
+        buffer
+    }
+
+    fn done(id: u64) -> Vec<u8> {
+        let mut buffer = b"updone\x01".to_vec();
+        buffer.extend_from_slice(&id.to_be_bytes());
+        buffer
+    }
+
+    fn u64s(response: &Response) -> Vec<u64> {
+        response
+            .1
+            .chunks_exact(8)
+            .map(|word| word.iter().fold(0, |value, b| value << 8 | *b as u64))
+            .collect()
+    }
+
+    #[test]
+    fn chunked_upload_resumes_and_refuses_bad_chunks() -> TestResult {
+        let fixture = Fixture::new("chunked")?;
+        let address = start_server(&fixture, None)?;
+        let table = fixture.table()?;
+        let (first, second) = table.split_at(table.len() / 2);
+        let half = first.len() as u64;
+
+        let begun = request(&address, &begin("t", table.len(), chunk_crc(&table)))?;
+        assert_eq!(begun.0, Status::Ok);
+        let id = u64s(&begun)[0];
+        assert_eq!(u64s(&begun), vec![id, 0]);
+        let sent = request(&address, &chunk(id, 0, first, chunk_crc(first)))?;
+        assert_eq!(u64s(&sent), vec![half]);
+
+        // Beginning the same upload again picks it up where it stopped
+        let resumed = request(&address, &begin("t", table.len(), chunk_crc(&table)))?;
+        assert_eq!(u64s(&resumed), vec![id, half]);
+
+        let corrupt = request(&address, &chunk(id, half, second, chunk_crc(second) ^ 1))?;
+        assert_eq!(corrupt.0, Status::ChunkMismatch);
+        assert!(String::from_utf8_lossy(&corrupt.1).ends_with(&format!("offset {}", half)));
+        let skipped = request(&address, &chunk(id, half + 1, second, chunk_crc(second)))?;
+        assert_eq!(skipped.0, Status::ChunkMismatch);
+        let overflowing = request(
+            &address,
+            &chunk(id, u64::MAX - 1, second, chunk_crc(second)),
+        )?;
+        assert_eq!(overflowing.0, Status::BadRequest);
+        // A chunk resent after its answer got lost is acknowledged, not written twice
+        let resent = request(&address, &chunk(id, 0, first, chunk_crc(first)))?;
+        assert_eq!(u64s(&resent), vec![half]);
+
+        let sent = request(&address, &chunk(id, half, second, chunk_crc(second)))?;
+        assert_eq!(u64s(&sent), vec![table.len() as u64]);
+        assert_eq!(request(&address, &done(id))?.0, Status::Ok);
+
+        let client = ClientConfig::new(&address);
+        hashassin_client::download(&client, "t", &fixture.path("download.rainbow"))?;
+        assert_eq!(fs::read(fixture.path("download.rainbow"))?, table);
+        Ok(())
+    }
 
-    for table in &table_data {
-        std::fs::write("temp_table.rainbow", table)?;
+    #[test]
+    fn chunked_upload_must_match_its_file_checksum() -> TestResult {
+        let fixture = Fixture::new("crc")?;
+        let address = start_server(&fixture, None)?;
+        let table = fixture.table()?;
 
-        match crack(
-            "temp_table.rainbow",
-            "temp_hashes.bin",
-            Some("cracked.txt"),
+        let begun = request(&address, &begin("t", table.len(), chunk_crc(&table) ^ 1))?;
+        let id = u64s(&begun)[0];
+        let sent = request(&address, &chunk(id, 0, &table, chunk_crc(&table)))?;
+        assert_eq!(u64s(&sent), vec![table.len() as u64]);
+        let refused = request(&address, &done(id))?;
+        assert_eq!(refused.0, Status::BadRequest);
+        assert!(String::from_utf8_lossy(&refused.1).contains("checksum it was begun with"));
+
+        // The refused upload is gone and nothing was stored
+        assert_eq!(request(&address, &done(id))?.0, Status::NotFound);
+        assert!(hashassin_client::list(&ClientConfig::new(&address))?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn unfinished_uploads_are_capped() -> TestResult {
+        let fixture = Fixture::new("capped")?;
+        let address = start_server(&fixture, None)?;
+        for i in 0..16u32 {
+            let begun = request(&address, &begin(&format!("t{}", i), 1000, i))?;
+            assert_eq!(begun.0, Status::Ok);
+        }
+        assert_eq!(
+            request(&address, &begin("one more", 1000, 0))?.0,
+            Status::TooLarge
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn streamed_crack_reports_every_hash_as_it_goes() -> TestResult {
+        let fixture = Fixture::new("streamed")?;
+        let address = start_server(&fixture, None)?;
+        let client = ClientConfig::new(&address);
+        hashassin_client::upload(&client, &fixture.path("table.rainbow"), "t", false)?;
+
+        // The table's seeds crack, passwords from elsewhere are all but certain to miss
+        let seeds = fs::read_to_string(fixture.path("seeds.txt"))?;
+        let misses = gen_passwords(4, 10, 1, Some(99))?;
+        fs::write(
+            fixture.path("mixed.txt"),
+            format!("{}\n{}", seeds, misses.join("\n")),
+        )?;
+        gen_hashes(
+            &fixture.path("mixed.txt"),
+            &fixture.path("mixed.bin"),
+            "md5",
             1,
-            cache.as_ref(),
-        ) {
-            Ok(_) => {
-                let result = std::fs::read_to_string("cracked.txt").unwrap_or_default();
-                combined_results.push(result);
-                found = true;
-                break;
-            }
-            Err(e) => {
-                info!("Table didn't find matches: {:?}", e);
-            }
+            &Hooks::default(),
+        )?;
+
+        let mut results = Vec::new();
+        let summary =
+            hashassin_client::crack(&client, &fixture.path("mixed.bin"), &[], |result| {
+                results.push(result)
+            })?;
+        assert_eq!(results.len(), 60);
+        let cracked: Vec<String> = results.iter().filter_map(|r| r.password.clone()).collect();
+        assert_eq!(cracked.len(), 50);
+        assert!(
+            cracked
+                .iter()
+                .all(|password| seeds.lines().any(|s| s == password))
+        );
+        assert!(summary.ends_with("cracked 50 of 60 hashes"), "{}", summary);
+        Ok(())
+    }
+
+    // The status a server refused a client call with, None if it went through
+    fn refusal<T>(result: ClientResult<T>) -> Option<Status> {
+        match result {
+            Err(ClientError::Server { status, .. }) => Some(status),
+            _ => None,
         }
     }
 
-    if found {
-        stream.write_all(combined_results.concat().as_bytes())?;
-    } else {
-        stream.write_all(b"No passwords cracked\n")?;
+    // A server accepting an admin token and two read tokens
+    fn start_with_tokens(fixture: &Fixture) -> Result<String, Box<dyn std::error::Error>> {
+        fs::write(
+            fixture.path("tokens.txt"),
+            "admin adm1n\nread reader-one\nread reader-two\n",
+        )?;
+        start_server(fixture, Some(fixture.path("tokens.txt")))
     }
 
-    Ok(())
+    #[test]
+    fn tokens_decide_what_a_connection_may_do() -> TestResult {
+        let fixture = Fixture::new("scopes")?;
+        let address = start_with_tokens(&fixture)?;
+        let token = |token: &str| ClientConfig::new(&address).with_token(Some(token.to_string()));
+        let table = fixture.path("table.rainbow");
+
+        let anonymous = ClientConfig::new(&address);
+        assert_eq!(
+            refusal(hashassin_client::list(&anonymous)),
+            Some(Status::Unauthorized)
+        );
+        assert_eq!(
+            refusal(hashassin_client::list(&token("guess"))),
+            Some(Status::Unauthorized)
+        );
+        assert_eq!(
+            refusal(hashassin_client::upload(
+                &token("reader-one"),
+                &table,
+                "t",
+                false
+            )),
+            Some(Status::Forbidden)
+        );
+        hashassin_client::upload(&token("adm1n"), &table, "t", false)?;
+        assert_eq!(hashassin_client::list(&token("reader-one"))?.len(), 1);
+        assert_eq!(
+            refusal(hashassin_client::delete(&token("reader-one"), "t")),
+            Some(Status::Forbidden)
+        );
+
+        let mut cracked = 0;
+        hashassin_client::crack(
+            &token("reader-two"),
+            &fixture.path("hashes.bin"),
+            &[],
+            |result| cracked += result.password.is_some() as usize,
+        )?;
+        assert_eq!(cracked, 50);
+        Ok(())
+    }
+
+    #[test]
+    fn challenges_answer_once() -> TestResult {
+        let fixture = Fixture::new("challenge")?;
+        let address = start_with_tokens(&fixture)?;
+
+        let mut stream = std::net::TcpStream::connect(&address)?;
+        let scope = hashassin_client::authenticate(&mut stream, "reader-one")?;
+        assert_eq!(scope, Scope::Read);
+
+        // An answer counts for the one challenge it was made for
+        let (_, challenge) = exchange(&mut stream, b"challg\x01")?;
+        let mut answer = b"auth\0\0\x01".to_vec();
+        answer.extend_from_slice(&sign_challenge(b"reader-one", &challenge)?);
+        assert_eq!(exchange(&mut stream, &answer)?, message(Status::Ok, "read"));
+        assert_eq!(exchange(&mut stream, &answer)?.0, Status::Unauthorized);
+        Ok(())
+    }
+
+    #[test]
+    fn jobs_belong_to_the_token_that_submitted_them() -> TestResult {
+        let fixture = Fixture::new("owners")?;
+        let address = start_with_tokens(&fixture)?;
+        let token = |token: &str| ClientConfig::new(&address).with_token(Some(token.to_string()));
+        let (admin, owner, other) = (token("adm1n"), token("reader-one"), token("reader-two"));
+        hashassin_client::upload(&admin, &fixture.path("table.rainbow"), "t", false)?;
+
+        let job = hashassin_client::submit(&owner, &fixture.path("hashes.bin"), &[])?;
+        let line = format!("{}\t", job);
+        assert!(hashassin_client::job_status(&owner, job)?.starts_with(&line));
+        assert!(hashassin_client::job_status(&admin, job)?.starts_with(&line));
+        assert!(hashassin_client::status(&admin)?.starts_with(&line));
+        assert!(!hashassin_client::status(&other)?.contains(&line));
+        assert_eq!(
+            refusal(hashassin_client::job_status(&other, job)),
+            Some(Status::NotFound)
+        );
+        assert_eq!(
+            refusal(hashassin_client::result(&other, job)),
+            Some(Status::NotFound)
+        );
+        assert_eq!(
+            refusal(hashassin_client::cancel(&other, job)),
+            Some(Status::NotFound)
+        );
+
+        let output = loop {
+            match hashassin_client::result(&owner, job) {
+                Err(ClientError::Server {
+                    status: Status::Pending,
+                    ..
+                }) => std::thread::sleep(std::time::Duration::from_millis(20)),
+                result => break result?,
+            }
+        };
+        let output = String::from_utf8_lossy(&output);
+        assert_eq!(output.lines().count(), 50);
+        assert!(!output.contains("NOT FOUND"));
+        Ok(())
+    }
 }
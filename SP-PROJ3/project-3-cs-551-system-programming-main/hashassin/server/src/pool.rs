@@ -0,0 +1,70 @@
+// Fixed pool of OS threads for CPU-bound work
+//
+// Cracking would stall the async runtime, so connection tasks hand it to this pool and
+// await the result. The queue is bounded, so once every worker is busy and the queue is
+// full, new requests wait instead of piling up more threads or memory.
+
+use std::panic::{AssertUnwindSafe, catch_unwind};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use tokio::sync::{mpsc, oneshot};
+use tracing::error;
+
+type Task = Box<dyn FnOnce() + Send>;
+
+// Jobs that may wait per worker before submitters have to wait
+const QUEUE_PER_WORKER: usize = 4;
+
+pub(crate) struct ComputePool {
+    sender: mpsc::Sender<Task>,
+}
+
+impl ComputePool {
+    // Starts `threads` workers, at least one
+    pub(crate) fn new(threads: usize) -> std::io::Result<ComputePool> {
+        let threads = threads.max(1);
+        let (sender, receiver) = mpsc::channel::<Task>(threads * QUEUE_PER_WORKER);
+        let receiver = Arc::new(Mutex::new(receiver));
+
+        for worker in 0..threads {
+            let receiver = Arc::clone(&receiver);
+            thread::Builder::new()
+                .name(format!("compute-{}", worker))
+                .spawn(move || {
+                    loop {
+                        // Only one idle worker waits on the queue at a time, the rest on the lock
+                        let task = match receiver.lock() {
+                            Ok(mut receiver) => receiver.blocking_recv(),
+                            Err(_) => None,
+                        };
+                        let Some(task) = task else { break };
+                        // A panicking job must not take the worker down with it
+                        if catch_unwind(AssertUnwindSafe(task)).is_err() {
+                            error!("Compute job panicked on worker {}", worker);
+                        }
+                    }
+                })?;
+        }
+
+        Ok(ComputePool { sender })
+    }
+
+    // Runs `job` on a worker and waits for its result
+    pub(crate) async fn run<F, T>(&self, job: F) -> std::io::Result<T>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        let (result_tx, result_rx) = oneshot::channel();
+        let task: Task = Box::new(move || {
+            let _ = result_tx.send(job());
+        });
+        self.sender
+            .send(task)
+            .await
+            .map_err(|_| std::io::Error::other("Compute pool has shut down"))?;
+        result_rx
+            .await
+            .map_err(|_| std::io::Error::other("Compute job failed"))
+    }
+}
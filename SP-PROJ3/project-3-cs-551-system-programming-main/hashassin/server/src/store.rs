@@ -0,0 +1,280 @@
+// Uploaded tables, optionally persisted in a data directory
+//
+// Without a data directory tables live on the heap until the server stops. With one,
+// every table is written to `tables/<hex name>.rainbow` and listed in `index`, one
+// `file<TAB>TableInfo line` per table, so a restarted server gets its tables back without
+// re-reading them. Tables are memory-mapped, not copied onto the heap. Files and the index
+// are replaced atomically: written to a temporary file, synced, then renamed over.
+// New tables are staged in `uploads`, which starts out empty on every start, and written
+// there without holding the store lock. Inserting one only renames it into place.
+
+use hashassin_core::TableInfo;
+use memmap2::Mmap;
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::Write;
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use tracing::{error, info};
+
+const INDEX_FILE: &str = "index";
+const TABLES_DIR: &str = "tables";
+const UPLOADS_DIR: &str = "uploads";
+
+// Numbers the staging files of single request uploads
+static NEXT_STAGED: AtomicU64 = AtomicU64::new(1);
+
+// The bytes of a table, on the heap or mapped from the data directory
+pub(crate) enum TableData {
+    Heap(Vec<u8>),
+    Mapped(Mmap),
+}
+
+impl Deref for TableData {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self {
+            TableData::Heap(data) => data,
+            TableData::Mapped(map) => map,
+        }
+    }
+}
+
+// A table with the summary list and info report, cheap to clone for crack jobs
+#[derive(Clone)]
+pub(crate) struct StoredTable {
+    pub(crate) data: Arc<TableData>,
+    pub(crate) info: TableInfo,
+}
+
+pub(crate) struct TableStore {
+    dir: Option<PathBuf>,
+    tables: HashMap<String, StoredTable>,
+}
+
+impl TableStore {
+    // Opens the store, loading every table the index lists when there is a data directory
+    pub(crate) fn open(dir: Option<&str>) -> std::io::Result<TableStore> {
+        let mut store = TableStore {
+            dir: dir.map(PathBuf::from),
+            tables: HashMap::new(),
+        };
+        // Uploads are only resumable while the server that started them runs
+        let staging = store.staging_dir();
+        if staging.exists() {
+            fs::remove_dir_all(&staging)?;
+        }
+        fs::create_dir_all(&staging)?;
+        let Some(dir) = store.dir.clone() else {
+            return Ok(store);
+        };
+        fs::create_dir_all(dir.join(TABLES_DIR))?;
+
+        let index = match fs::read_to_string(dir.join(INDEX_FILE)) {
+            Ok(index) => index,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(e),
+        };
+        for line in index.lines().filter(|line| !line.is_empty()) {
+            match load_entry(&dir, line) {
+                Ok(table) => {
+                    store.tables.insert(table.info.name.clone(), table);
+                }
+                Err(e) => error!("Skipping table index entry '{}': {}", line, e),
+            }
+        }
+        info!(
+            "Loaded {} rainbow tables from '{}'",
+            store.tables.len(),
+            dir.display()
+        );
+        Ok(store)
+    }
+
+    // Where chunked uploads collect their data, on the same file system as the tables
+    pub(crate) fn staging_dir(&self) -> PathBuf {
+        match &self.dir {
+            Some(dir) => dir.join(UPLOADS_DIR),
+            None => std::env::temp_dir().join(format!("hashassin-uploads-{}", std::process::id())),
+        }
+    }
+
+    pub(crate) fn get(&self, name: &str) -> Option<&StoredTable> {
+        self.tables.get(name)
+    }
+
+    pub(crate) fn contains(&self, name: &str) -> bool {
+        self.tables.contains_key(name)
+    }
+
+    pub(crate) fn tables(&self) -> impl Iterator<Item = &StoredTable> {
+        self.tables.values()
+    }
+
+    // How to prepare new tables for this store, taken out of the lock before the slow part
+    pub(crate) fn stager(&self) -> Stager {
+        Stager {
+            persistent: self.dir.is_some(),
+            staging: self.staging_dir(),
+        }
+    }
+
+    // Adds or replaces a staged table, only renames and maps it while the lock is held
+    pub(crate) fn insert(&mut self, info: TableInfo, staged: Staged) -> std::io::Result<()> {
+        let data = match (&self.dir, staged) {
+            (Some(dir), Staged::File(staged)) => {
+                let path = dir.join(TABLES_DIR).join(file_name(&info.name));
+                fs::rename(&staged, &path)?;
+                File::open(dir.join(TABLES_DIR))?.sync_all()?;
+                TableData::Mapped(map_file(&path)?)
+            }
+            (None, Staged::Heap(data)) => TableData::Heap(data),
+            (_, staged) => {
+                staged.discard();
+                return Err(std::io::Error::other(
+                    "table was staged for a different store",
+                ));
+            }
+        };
+        self.tables.insert(
+            info.name.clone(),
+            StoredTable {
+                data: Arc::new(data),
+                info,
+            },
+        );
+        self.write_index()
+    }
+
+    // Removes a table, returns false when there was none of that name
+    // Running crack jobs keep their mapping of a removed table until they finish
+    pub(crate) fn remove(&mut self, name: &str) -> std::io::Result<bool> {
+        if self.tables.remove(name).is_none() {
+            return Ok(false);
+        }
+        self.write_index()?;
+        if let Some(dir) = &self.dir {
+            fs::remove_file(dir.join(TABLES_DIR).join(file_name(name)))?;
+        }
+        Ok(true)
+    }
+
+    fn write_index(&self) -> std::io::Result<()> {
+        let Some(dir) = &self.dir else {
+            return Ok(());
+        };
+        let mut names: Vec<&String> = self.tables.keys().collect();
+        names.sort();
+        let mut index = String::new();
+        for name in names {
+            let info = &self.tables[name].info;
+            index.push_str(&format!("{}\t{}\n", file_name(name), info.to_line()));
+        }
+        write_atomic(&dir.join(INDEX_FILE), index.as_bytes())
+    }
+}
+
+// Prepares tables for a store without holding its lock
+pub(crate) struct Stager {
+    persistent: bool,
+    staging: PathBuf,
+}
+
+// A complete table ready to be inserted
+pub(crate) enum Staged {
+    // A synced file in the staging directory, for stores with a data directory
+    File(PathBuf),
+    Heap(Vec<u8>),
+}
+
+impl Stager {
+    // Stages a table received in one piece, a persistent store gets it written and synced
+    pub(crate) fn bytes(&self, data: Vec<u8>) -> std::io::Result<Staged> {
+        if !self.persistent {
+            return Ok(Staged::Heap(data));
+        }
+        let id = NEXT_STAGED.fetch_add(1, Ordering::Relaxed);
+        let path = self.staging.join(format!("single-{}.part", id));
+        let written = File::create(&path).and_then(|mut file| {
+            file.write_all(&data)?;
+            file.sync_all()
+        });
+        if let Err(e) = written {
+            let _ = fs::remove_file(&path);
+            return Err(e);
+        }
+        Ok(Staged::File(path))
+    }
+
+    // Stages a complete, synced file from the staging directory, a heap store reads it in
+    pub(crate) fn file(&self, path: &Path) -> std::io::Result<Staged> {
+        if self.persistent {
+            return Ok(Staged::File(path.to_path_buf()));
+        }
+        let data = fs::read(path)?;
+        fs::remove_file(path)?;
+        Ok(Staged::Heap(data))
+    }
+}
+
+impl Staged {
+    // Drops a staged table that will not be inserted
+    pub(crate) fn discard(self) {
+        if let Staged::File(path) = self {
+            let _ = fs::remove_file(path);
+        }
+    }
+}
+
+// Table names can hold any character, so files are named by their hex encoding
+fn file_name(name: &str) -> String {
+    format!("{}.rainbow", hex::encode(name))
+}
+
+fn load_entry(dir: &Path, line: &str) -> Result<StoredTable, Box<dyn std::error::Error>> {
+    let (file, info) = line.split_once('\t').ok_or("missing file name")?;
+    let info = TableInfo::from_line(info)?;
+    if file != file_name(&info.name) {
+        return Err(format!("file '{}' does not match the table name", file).into());
+    }
+    let path = dir.join(TABLES_DIR).join(file);
+    let map = map_file(&path)?;
+    if map.len() as u64 != info.size {
+        return Err(format!(
+            "'{}' is {} bytes, the index says {}",
+            path.display(),
+            map.len(),
+            info.size
+        )
+        .into());
+    }
+    Ok(StoredTable {
+        data: Arc::new(TableData::Mapped(map)),
+        info,
+    })
+}
+
+pub(crate) fn map_file(path: &Path) -> std::io::Result<Mmap> {
+    let file = File::open(path)?;
+    // SAFETY: table files are only ever replaced by renaming a new file over them, never
+    // modified in place, and staged uploads are complete before they are mapped, so the
+    // mapped bytes do not change while the map is alive
+    unsafe { Mmap::map(&file) }
+}
+
+// Writes `bytes` to a temporary file next to `path`, syncs it and renames it into place
+fn write_atomic(path: &Path, bytes: &[u8]) -> std::io::Result<()> {
+    let tmp = path.with_extension("tmp");
+    let mut file = File::create(&tmp)?;
+    file.write_all(bytes)?;
+    file.sync_all()?;
+    fs::rename(&tmp, path)?;
+    // Sync the directory too so the rename itself survives a crash
+    if let Some(parent) = path.parent() {
+        File::open(parent)?.sync_all()?;
+    }
+    Ok(())
+}
@@ -0,0 +1,175 @@
+// Checks an uploaded table while it arrives
+//
+// The header is parsed as soon as enough bytes are in, so uploads that are not a rainbow
+// table, use an unknown algorithm, or whose v5 body length disagrees with the declared
+// upload size are refused before the body is read. The body is checked in full once it is
+// complete, by TableInfo::from_table.
+
+use hashassin_core::{TableHeader, digest_len};
+
+// Upper bound on the size of any table header: magic, fields, a 255 byte algorithm name,
+// the seed and the v5 trailer all fit comfortably
+const HEADER_PROBE: usize = 512;
+
+pub(crate) struct TableValidator {
+    declared: u64,
+    received: u64,
+    // The start of the upload, kept until the header parses
+    probe: Vec<u8>,
+    header: Option<TableHeader>,
+}
+
+impl TableValidator {
+    pub(crate) fn new(declared: u64) -> TableValidator {
+        TableValidator {
+            declared,
+            received: 0,
+            probe: Vec::new(),
+            header: None,
+        }
+    }
+
+    // Takes the next bytes of the upload, failing as soon as it cannot be a valid table
+    pub(crate) fn feed(&mut self, chunk: &[u8]) -> Result<(), String> {
+        self.received += chunk.len() as u64;
+        if self.received > self.declared {
+            return Err(format!(
+                "Upload is longer than the declared {} bytes",
+                self.declared
+            ));
+        }
+        if self.header.is_some() {
+            return Ok(());
+        }
+
+        let wanted = HEADER_PROBE.min(self.declared as usize);
+        let take = chunk.len().min(wanted.saturating_sub(self.probe.len()));
+        self.probe.extend_from_slice(&chunk[..take]);
+        if self.probe.len() < wanted {
+            return Ok(());
+        }
+
+        let (header, pos) = TableHeader::parse(&self.probe).map_err(|e| e.to_string())?;
+        digest_len(&header.algorithm).map_err(|e| e.to_string())?;
+        if header.version >= 5 && pos as u64 + header.body_len != self.declared {
+            return Err(format!(
+                "Rainbow table header and body need {} bytes, upload is {}",
+                pos as u64 + header.body_len,
+                self.declared
+            ));
+        }
+        self.header = Some(header);
+        self.probe = Vec::new();
+        Ok(())
+    }
+
+    // Called once every declared byte has arrived
+    pub(crate) fn finish(&self) -> Result<(), String> {
+        if self.received != self.declared {
+            return Err(format!(
+                "Upload ended after {} of {} bytes",
+                self.received, self.declared
+            ));
+        }
+        match self.header {
+            Some(_) => Ok(()),
+            None => Err("Upload is too short to be a rainbow table".to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hashassin_core::{RainbowTable, index_start};
+
+    // A v`version` md5 table of 4 character passwords, long enough to outgrow the probe
+    fn table(version: u8, algorithm: &str) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+        let allowed: Vec<u8> = (32u8..=126u8).collect();
+        let chains = (0..200u64)
+            .map(|i| {
+                let start = index_start(1, i, 4, 4, &allowed);
+                let end = index_start(2, i, 4, 4, &allowed);
+                (i, start, end)
+            })
+            .collect();
+        let mut table = RainbowTable {
+            header: TableHeader {
+                version,
+                algorithm: algorithm.to_string(),
+                min_len: 4,
+                max_len: 4,
+                charset_size: allowed.len() as u128,
+                num_links: 10,
+                ascii_offset: 32,
+                flags: 0,
+                table_seed: 0,
+                index_bytes: 0,
+                point_bytes: 0,
+                dp_bits: 0,
+                body_len: 0,
+                body_crc: 0,
+            },
+            chains,
+        };
+        table.to_bytes()
+    }
+
+    // Feeds `data` in small pieces and returns the first error
+    fn feed_all(validator: &mut TableValidator, data: &[u8]) -> Result<(), String> {
+        data.chunks(7).try_for_each(|piece| validator.feed(piece))
+    }
+
+    #[test]
+    fn valid_tables_pass_in_any_piece_size() -> Result<(), Box<dyn std::error::Error>> {
+        for version in [2, 4, 5] {
+            let data = table(version, "md5")?;
+            assert!(data.len() > HEADER_PROBE);
+            let mut validator = TableValidator::new(data.len() as u64);
+            feed_all(&mut validator, &data)?;
+            validator.finish()?;
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn bad_headers_fail_before_the_body() -> Result<(), Box<dyn std::error::Error>> {
+        // Not a table at all
+        let mut validator = TableValidator::new(1 << 20);
+        assert!(validator.feed(&[b'x'; HEADER_PROBE]).is_err());
+
+        // A table of an algorithm the server cannot crack
+        let data = table(5, "rot13")?;
+        let mut validator = TableValidator::new(data.len() as u64);
+        let error = validator.feed(&data[..HEADER_PROBE]).err();
+        assert!(error.is_some_and(|e| e.contains("rot13")));
+        Ok(())
+    }
+
+    #[test]
+    fn v5_body_length_must_match_the_upload_size() -> Result<(), Box<dyn std::error::Error>> {
+        let data = table(5, "md5")?;
+        let mut validator = TableValidator::new(data.len() as u64 + 10);
+        let error = validator.feed(&data[..HEADER_PROBE]).err();
+        assert!(error.is_some_and(|e| e.contains("header and body need")));
+
+        // v4 tables do not record their body length, so only the end can tell
+        let data = table(4, "md5")?;
+        let mut validator = TableValidator::new(data.len() as u64 + 10);
+        feed_all(&mut validator, &data)?;
+        assert!(validator.finish().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn uploads_must_be_exactly_the_declared_size() -> Result<(), Box<dyn std::error::Error>> {
+        let data = table(5, "md5")?;
+        let mut validator = TableValidator::new(data.len() as u64);
+        feed_all(&mut validator, &data[..data.len() / 2])?;
+        assert!(validator.finish().is_err());
+        feed_all(&mut validator, &data[data.len() / 2..])?;
+        validator.finish()?;
+        assert!(validator.feed(b"!").is_err());
+        Ok(())
+    }
+}